@@ -0,0 +1,5 @@
+output = open("output.txt").read().split()
+answer = open("answer.txt").read().split()
+if sorted(output) != sorted(answer):
+    print("expected", " ".join(answer), "in any order")
+    exit(1)
@@ -0,0 +1,19 @@
+(module
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (local $read i32)
+    (loop $copy
+      (i32.store (i32.const 0) (i32.const 64))
+      (i32.store (i32.const 4) (i32.const 4096))
+      (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+      (local.set $read (i32.load (i32.const 8)))
+      (if (i32.gt_u (local.get $read) (i32.const 0))
+        (then
+          (i32.store (i32.const 16) (i32.const 64))
+          (i32.store (i32.const 20) (local.get $read))
+          (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))
+          (br $copy))))))
@@ -0,0 +1,19 @@
+import sys
+
+secret = 37
+print("guess a number between 1 and 100", flush=True)
+guesses = 0
+while True:
+    line = sys.stdin.readline()
+    if not line:
+        print("gave up after", guesses, "guesses", file=sys.stderr, flush=True)
+        break
+    guesses += 1
+    guess = int(line)
+    if guess < secret:
+        print("higher", flush=True)
+    elif guess > secret:
+        print("lower", flush=True)
+    else:
+        print("correct", flush=True)
+        break
@@ -0,0 +1,5 @@
+public class BrokenGuess {
+  public static void main(String[] args) {
+    System.out.println("? " + guess)
+  }
+}
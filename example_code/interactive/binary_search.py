@@ -0,0 +1,13 @@
+import sys
+
+low, high = 1, 100
+while low <= high:
+    guess = (low + high) // 2
+    print("?", guess, flush=True)
+    reply = sys.stdin.readline().strip()
+    if reply == "=":
+        break
+    if reply == "<":
+        high = guess - 1
+    else:
+        low = guess + 1
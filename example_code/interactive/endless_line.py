@@ -0,0 +1,5 @@
+import sys
+
+while True:
+    sys.stdout.write("? " * 4096)
+    sys.stdout.flush()
@@ -0,0 +1,5 @@
+import sys
+
+sys.stdout.buffer.write(b"? \xff\xfe\n")
+sys.stdout.flush()
+sys.stdin.readline()
@@ -0,0 +1,5 @@
+import time
+
+for _ in range(7):
+    print("? 1", flush=True)
+time.sleep(10)
@@ -0,0 +1,13 @@
+import sys
+
+secret = int(open("input.txt").read())
+for _ in range(7):
+    line = sys.stdin.readline()
+    if not line:
+        sys.exit(1)
+    guess = int(line.split()[1])
+    if guess == secret:
+        print("=", flush=True)
+        sys.exit(0)
+    print("<" if secret < guess else ">", flush=True)
+sys.exit(1)
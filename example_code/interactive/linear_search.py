@@ -0,0 +1,6 @@
+import sys
+
+for guess in range(1, 101):
+    print("?", guess, flush=True)
+    if sys.stdin.readline().strip() == "=":
+        break
@@ -0,0 +1,18 @@
+import os
+import sys
+
+low, high = 1, 100
+while low <= high:
+    guess = (low + high) // 2
+    print("?", guess, flush=True)
+    reply = sys.stdin.readline().strip()
+    if reply == "=":
+        break
+    if reply == "<":
+        high = guess - 1
+    else:
+        low = guess + 1
+os.close(1)
+os.close(2)
+while True:
+    pass
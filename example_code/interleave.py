@@ -0,0 +1,5 @@
+import sys
+
+print("reading input", flush=True)
+print("warning: input is empty", file=sys.stderr, flush=True)
+print("done", flush=True)
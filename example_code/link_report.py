@@ -0,0 +1,9 @@
+import os
+
+os.makedirs("reports", exist_ok=True)
+with open("reports/summary.txt", "w") as report:
+    report.write("ok\n")
+os.symlink("/etc/passwd", "reports/passwd.txt")
+os.symlink("..", "reports/parent")
+os.symlink(".", "reports/loop")
+print("done")
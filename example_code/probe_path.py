@@ -0,0 +1,4 @@
+import os
+
+path = input()
+print("visible" if os.path.exists(path) else "hidden")
@@ -0,0 +1,11 @@
+import os
+import sys
+
+numbers = [int(sys.stdin.readline()) for _ in range(3)]
+os.makedirs("reports", exist_ok=True)
+with open("reports/summary.txt", "w") as report:
+    report.write(f"count={len(numbers)}\n")
+    report.write(f"sum={sum(numbers)}\n")
+with open("scratch.tmp", "w") as scratch:
+    scratch.write("ignored\n")
+print(sum(numbers))
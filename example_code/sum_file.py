@@ -0,0 +1,2 @@
+with open("numbers.txt") as numbers:
+    print(sum(int(line) for line in numbers if line.strip()))
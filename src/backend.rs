@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::AsyncWrite;
+use serde::Deserialize;
+use shiplift::tty::TtyChunk;
+
+use crate::sandbox::{BuildProgress, SandboxError};
+
+mod cgroup;
+pub mod docker;
+pub mod local;
+mod tunnel;
+
+pub use docker::{DaemonVersion, DockerBackend, DockerEndpoint, TlsConfig};
+pub use local::{Isolation, LocalBackend, LocalLimits};
+
+#[async_trait]
+pub trait ContainerBackend: Send + Sync {
+    fn endpoint(&self) -> String;
+
+    async fn ping(&self) -> Result<(), SandboxError>;
+
+    async fn version(&self) -> Result<DaemonVersion, SandboxError>;
+
+    async fn image_labels(&self, image_tag: &str) -> Result<HashMap<String, String>, SandboxError>;
+
+    async fn build_image(
+        &self,
+        context: &Path,
+        image_tag: &str,
+        progress: Option<&BuildProgress>,
+    ) -> Result<(), SandboxError>;
+
+    async fn load_image(&self, archive: &Path) -> Result<(), SandboxError>;
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>, SandboxError>;
+
+    async fn list_dangling_images(&self) -> Result<Vec<ImageSummary>, SandboxError>;
+
+    async fn remove_image(&self, image_id: &str) -> Result<(), SandboxError>;
+
+    async fn create_container(&self, spec: &ContainerSpec<'_>) -> Result<String, SandboxError>;
+
+    async fn attach_container<'a>(
+        &'a self,
+        container_id: &str,
+    ) -> Result<AttachedContainer<'a>, SandboxError>;
+
+    async fn copy_into_container(
+        &self,
+        container_id: &str,
+        archive: Vec<u8>,
+    ) -> Result<(), SandboxError>;
+
+    async fn copy_from_container(
+        &self,
+        container_id: &str,
+        path: &Path,
+        dest: &Path,
+    ) -> Result<(), SandboxError>;
+
+    async fn start_container(&self, container_id: &str) -> Result<(), SandboxError>;
+
+    async fn wait_container(&self, container_id: &str) -> Result<ContainerExit, SandboxError>;
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), SandboxError>;
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), SandboxError>;
+}
+
+pub const CONTEXT_HASH_LABEL: &str = "rustychains.context-hash";
+pub const OWNER_LABEL: &str = "rustychains.owner";
+pub const OWNER: &str = "rustychains";
+pub const SANDBOX_ID_LABEL: &str = "rustychains.sandbox-id";
+pub const CREATED_LABEL: &str = "rustychains.created";
+
+pub struct ContainerSpec<'a> {
+    pub image_tag: &'a str,
+    pub host_dir: &'a Path,
+    pub workdir: &'a Path,
+    pub cmd: &'a [String],
+    pub limits: ResourceLimits,
+    pub network: NetworkPolicy,
+    pub sandbox_id: &'a str,
+    pub transfer: TransferMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkPolicy {
+    #[default]
+    Enabled,
+    Disabled,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferMode {
+    #[default]
+    BindMount,
+    Copy,
+}
+
+#[derive(Clone, Debug)]
+pub struct ContainerSummary {
+    pub id: String,
+    pub created: SystemTime,
+    pub labels: HashMap<String, String>,
+    pub running: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageSummary {
+    pub id: String,
+    pub created: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    pub memory_bytes: Option<u64>,
+    pub cpus: Option<f64>,
+}
+
+pub struct AttachedContainer<'a> {
+    pub output: BoxStream<'a, Result<TtyChunk, SandboxError>>,
+    pub stdin: Pin<Box<dyn AsyncWrite + Send + 'a>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ContainerExit {
+    pub exit_code: u64,
+    pub oom_killed: bool,
+    pub usage: ResourceUsage,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub cpu_user: Option<Duration>,
+    pub cpu_system: Option<Duration>,
+    pub peak_memory_bytes: Option<u64>,
+    pub io_read_bytes: Option<u64>,
+    pub io_write_bytes: Option<u64>,
+    pub wall_time: Option<Duration>,
+}
+
+impl ResourceLimits {
+    pub fn or(self, defaults: ResourceLimits) -> Self {
+        ResourceLimits {
+            memory_bytes: self.memory_bytes.or(defaults.memory_bytes),
+            cpus: self.cpus.or(defaults.cpus),
+        }
+    }
+}
+
+impl ResourceUsage {
+    pub fn accumulate(&mut self, other: &ResourceUsage) {
+        self.cpu_user = sum(self.cpu_user, other.cpu_user);
+        self.cpu_system = sum(self.cpu_system, other.cpu_system);
+        self.peak_memory_bytes = self.peak_memory_bytes.max(other.peak_memory_bytes);
+        self.io_read_bytes = sum(self.io_read_bytes, other.io_read_bytes);
+        self.io_write_bytes = sum(self.io_write_bytes, other.io_write_bytes);
+        self.wall_time = sum(self.wall_time, other.wall_time);
+    }
+}
+
+fn sum<T: std::ops::Add<Output = T>>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::ResourceUsage;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// the cgroup a container runs in, read straight from the host so that usage does not depend on
+// the stats format of the daemon; cgroup v2 keeps everything in one directory while v1 has one
+// hierarchy per controller
+pub(crate) struct ContainerCgroup {
+    unified: Option<PathBuf>,
+    memory: Option<PathBuf>,
+    cpuacct: Option<PathBuf>,
+    blkio: Option<PathBuf>,
+}
+
+impl ContainerCgroup {
+    // the daemon creates the cgroup before the container starts and names it after the
+    // container, so it can be found without a pid even when the process is already gone; the
+    // layouts are those of docker and podman with either the systemd or the cgroupfs driver
+    pub(crate) fn by_id(container_id: &str) -> Option<Self> {
+        let root = Path::new(CGROUP_ROOT);
+        [
+            format!("system.slice/docker-{container_id}.scope"),
+            format!("docker/{container_id}"),
+            format!("machine.slice/libpod-{container_id}.scope"),
+            format!("libpod_parent/libpod-{container_id}"),
+        ]
+        .into_iter()
+        .find_map(|path| {
+            let unified = root.join(&path);
+            if unified.join("cgroup.controllers").is_file() {
+                return Some(ContainerCgroup {
+                    unified: Some(unified),
+                    memory: None,
+                    cpuacct: None,
+                    blkio: None,
+                });
+            }
+            let controller =
+                |name: &str| Some(root.join(name).join(&path)).filter(|dir| dir.is_dir());
+            let cgroup = ContainerCgroup {
+                unified: None,
+                memory: controller("memory"),
+                cpuacct: controller("cpuacct"),
+                blkio: controller("blkio"),
+            };
+            (cgroup.memory.is_some() || cgroup.cpuacct.is_some()).then_some(cgroup)
+        })
+    }
+
+    // only cgroups named after the container are used, a pid reported by a remote daemon may
+    // belong to some unrelated process on this host
+    pub(crate) fn find(pid: u64, container_id: &str) -> Option<Self> {
+        let cgroups = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+        let mut cgroup = ContainerCgroup {
+            unified: None,
+            memory: None,
+            cpuacct: None,
+            blkio: None,
+        };
+        for line in cgroups.lines() {
+            let mut fields = line.splitn(3, ':');
+            let (Some(_), Some(controllers), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if !path.contains(container_id) {
+                continue;
+            }
+            let path = path.trim_start_matches('/');
+            if controllers.is_empty() {
+                cgroup.unified = Some(Path::new(CGROUP_ROOT).join(path));
+            }
+            for controller in controllers.split(',') {
+                let dir = Path::new(CGROUP_ROOT).join(controller).join(path);
+                match controller {
+                    "memory" => cgroup.memory = Some(dir),
+                    "cpuacct" => cgroup.cpuacct = Some(dir),
+                    "blkio" => cgroup.blkio = Some(dir),
+                    _ => {}
+                }
+            }
+        }
+        let found = [&cgroup.unified, &cgroup.memory, &cgroup.cpuacct]
+            .into_iter()
+            .any(Option::is_some);
+        found.then_some(cgroup)
+    }
+
+    // counters only grow, so every sample replaces the last; the cgroup disappears with the
+    // container and whatever was read until then is kept
+    pub(crate) fn sample(&self, usage: &mut ResourceUsage) {
+        if let Some(peak) = self.peak_memory() {
+            usage.peak_memory_bytes = Some(peak.max(usage.peak_memory_bytes.unwrap_or(0)));
+        }
+        if let Some((user, system)) = self.cpu_time() {
+            usage.cpu_user = Some(user);
+            usage.cpu_system = Some(system);
+        }
+        if let Some((read, write)) = self.io_bytes() {
+            usage.io_read_bytes = Some(read);
+            usage.io_write_bytes = Some(write);
+        }
+    }
+
+    fn peak_memory(&self) -> Option<u64> {
+        let unified = self.unified.as_ref().and_then(|dir| {
+            // memory.peak only exists since linux 5.19, the current usage is the fallback
+            read_number(&dir.join("memory.peak"))
+                .or_else(|| read_number(&dir.join("memory.current")))
+        });
+        unified.or_else(|| {
+            let dir = self.memory.as_ref()?;
+            read_number(&dir.join("memory.max_usage_in_bytes"))
+        })
+    }
+
+    fn cpu_time(&self) -> Option<(Duration, Duration)> {
+        let unified = self.unified.as_ref().and_then(|dir| {
+            let stat = fs::read_to_string(dir.join("cpu.stat")).ok()?;
+            let user = keyed_value(&stat, "user_usec")?;
+            let system = keyed_value(&stat, "system_usec")?;
+            Some((Duration::from_micros(user), Duration::from_micros(system)))
+        });
+        unified.or_else(|| {
+            let stat = fs::read_to_string(self.cpuacct.as_ref()?.join("cpuacct.stat")).ok()?;
+            // SAFETY: sysconf has no preconditions
+            let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+            let ticks = |key| {
+                Some(Duration::from_millis(
+                    keyed_value(&stat, key)? * 1000 / ticks_per_sec,
+                ))
+            };
+            Some((ticks("user")?, ticks("system")?))
+        })
+    }
+
+    fn io_bytes(&self) -> Option<(u64, u64)> {
+        let unified = self.unified.as_ref().and_then(|dir| {
+            let stat = fs::read_to_string(dir.join("io.stat")).ok()?;
+            let sum = |key: &str| {
+                stat.split_whitespace()
+                    .filter_map(|field| field.strip_prefix(key)?.parse::<u64>().ok())
+                    .sum::<u64>()
+            };
+            Some((sum("rbytes="), sum("wbytes=")))
+        });
+        unified.or_else(|| {
+            let dir = self.blkio.as_ref()?;
+            let stat = fs::read_to_string(dir.join("blkio.throttle.io_service_bytes")).ok()?;
+            let sum = |op: &str| {
+                stat.lines()
+                    .filter_map(|line| {
+                        let mut fields = line.split_whitespace();
+                        let (_, kind, value) = (fields.next()?, fields.next()?, fields.next()?);
+                        kind.eq_ignore_ascii_case(op)
+                            .then(|| value.parse::<u64>().ok())?
+                    })
+                    .sum::<u64>()
+            };
+            Some((sum("read"), sum("write")))
+        })
+    }
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn keyed_value(stat: &str, key: &str) -> Option<u64> {
+    stat.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        (name == key).then(|| value.trim().parse().ok())?
+    })
+}
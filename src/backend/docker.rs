@@ -0,0 +1,640 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use hyper::{header, Body, Request};
+use hyperlocal::UnixConnector;
+use serde_json::Value;
+use shiplift::{BuildOptions, ContainerFilter, ContainerListOptions, ContainerOptions, Docker};
+use shiplift::{ImageFilter, ImageListOptions, RmContainerOptions, Uri};
+use tokio::time;
+use tokio_util::io::ReaderStream;
+
+use super::cgroup::ContainerCgroup;
+use super::tunnel::Tunnel;
+use super::{AttachedContainer, ContainerBackend, ContainerExit, ContainerSpec, ResourceUsage};
+use super::{ContainerSummary, ImageSummary, CONTEXT_HASH_LABEL, CREATED_LABEL};
+use super::{NetworkPolicy, TransferMode, OWNER, OWNER_LABEL, SANDBOX_ID_LABEL};
+use crate::sandbox::{BuildProgress, SandboxError};
+
+// podman serves the same api, so it runs through this backend under its own name
+pub struct DockerBackend {
+    docker: Docker,
+    endpoint: DockerEndpoint,
+    name: &'static str,
+    // the unix socket the client talks to, whenever there is one
+    socket: Option<PathBuf>,
+    _tunnel: Option<Tunnel>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum DockerEndpoint {
+    #[default]
+    Default,
+    Unix(PathBuf),
+    Tcp {
+        url: String,
+        tls: Option<TlsConfig>,
+    },
+}
+
+// the daemon is always verified against ca.pem
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_dir: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+pub struct DaemonVersion {
+    pub version: String,
+    pub api_version: String,
+    pub os: String,
+    pub arch: String,
+}
+
+impl DockerBackend {
+    pub fn connect(endpoint: DockerEndpoint) -> Result<Self, SandboxError> {
+        let (docker, tunnel) = connect(&endpoint)?;
+        Ok(DockerBackend {
+            docker,
+            socket: daemon_socket(&endpoint, tunnel.as_ref()),
+            endpoint,
+            name: "docker",
+            _tunnel: tunnel,
+        })
+    }
+
+    pub fn podman(socket: Option<PathBuf>) -> Result<Self, SandboxError> {
+        let endpoint = socket.map_or_else(podman_endpoint, DockerEndpoint::Unix);
+        let (docker, tunnel) = connect(&endpoint)?;
+        Ok(DockerBackend {
+            docker,
+            socket: daemon_socket(&endpoint, tunnel.as_ref()),
+            endpoint,
+            name: "podman",
+            _tunnel: tunnel,
+        })
+    }
+
+    fn daemon(&self) -> String {
+        format!("{} daemon at {}", self.name, self.endpoint)
+    }
+}
+
+fn podman_endpoint() -> DockerEndpoint {
+    if let Ok(host) = env::var("CONTAINER_HOST") {
+        return match host.strip_prefix("unix://") {
+            Some(path) => DockerEndpoint::Unix(path.into()),
+            None => DockerEndpoint::Tcp {
+                url: host,
+                tls: None,
+            },
+        };
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) => DockerEndpoint::Unix(Path::new(&runtime_dir).join("podman/podman.sock")),
+        Err(_) => DockerEndpoint::Unix("/run/podman/podman.sock".into()),
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for DockerBackend {
+    fn endpoint(&self) -> String {
+        self.endpoint.to_string()
+    }
+
+    async fn ping(&self) -> Result<(), SandboxError> {
+        self.docker
+            .ping()
+            .await
+            .map_err(|err| SandboxError::DaemonUnreachable {
+                daemon: self.daemon(),
+                source: err,
+            })?;
+        Ok(())
+    }
+
+    async fn version(&self) -> Result<DaemonVersion, SandboxError> {
+        let version =
+            self.docker
+                .version()
+                .await
+                .map_err(|err| SandboxError::DaemonUnreachable {
+                    daemon: self.daemon(),
+                    source: err,
+                })?;
+        Ok(DaemonVersion {
+            version: version.version,
+            api_version: version.api_version,
+            os: version.os,
+            arch: version.arch,
+        })
+    }
+
+    async fn image_labels(&self, image_tag: &str) -> Result<HashMap<String, String>, SandboxError> {
+        let details = self
+            .docker
+            .images()
+            .get(image_tag)
+            .inspect()
+            .await
+            .map_err(|err| SandboxError::MissingImage {
+                image_tag: image_tag.to_owned(),
+                source: err,
+            })?;
+        Ok(details.config.labels.unwrap_or_default())
+    }
+
+    async fn build_image(
+        &self,
+        context: &Path,
+        image_tag: &str,
+        progress: Option<&BuildProgress>,
+    ) -> Result<(), SandboxError> {
+        let options = BuildOptions::builder(context.display().to_string())
+            .tag(image_tag)
+            .build();
+        let mut stream = self.docker.images().build(&options);
+        let mut log = BuildLog::default();
+        while let Some(build_result) = stream.next().await {
+            let message = build_result.map_err(SandboxError::BuildImageStream)?;
+            for line in log.push(&message) {
+                if let Some(callback) = progress {
+                    callback(&line);
+                }
+            }
+            if let Some(error) = build_error(&message) {
+                return Err(log.into_error(error));
+            }
+        }
+        Ok(())
+    }
+
+    // archives often run to gigabytes, so they are streamed to the daemon's socket; shiplift
+    // reads the whole archive into memory first, which is only left to daemons reached over a
+    // tcp DOCKER_HOST, and then off the executor
+    async fn load_image(&self, archive: &Path) -> Result<(), SandboxError> {
+        let read_err = |err| SandboxError::ReadImageArchive {
+            path: archive.to_owned(),
+            source: err,
+        };
+        let load_err = |err| SandboxError::LoadImage {
+            path: archive.to_owned(),
+            source: err,
+        };
+        let messages = match &self.socket {
+            Some(socket) => {
+                let tarball = tokio::fs::File::open(archive).await.map_err(read_err)?;
+                post_archive(socket, tarball).await.map_err(load_err)?
+            }
+            None => {
+                let path = archive.to_owned();
+                let tarball = tokio::task::spawn_blocking(move || fs::read(path))
+                    .await
+                    .map_err(io::Error::other)
+                    .and_then(|result| result)
+                    .map_err(read_err)?;
+                self.docker
+                    .images()
+                    .import(io::Cursor::new(tarball))
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .map_err(load_err)?
+            }
+        };
+        for message in messages {
+            if let Some(error) = build_error(&message) {
+                return Err(SandboxError::LoadImageFailed {
+                    path: archive.to_owned(),
+                    message: error,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>, SandboxError> {
+        let options = ContainerListOptions::builder()
+            .all()
+            .filter(vec![ContainerFilter::Label(
+                OWNER_LABEL.to_owned(),
+                OWNER.to_owned(),
+            )])
+            .build();
+        let containers = self
+            .docker
+            .containers()
+            .list(&options)
+            .await
+            .map_err(SandboxError::ListContainers)?;
+        Ok(containers
+            .into_iter()
+            .map(|container| ContainerSummary {
+                id: container.id,
+                created: container.created.into(),
+                labels: container.labels,
+                running: matches!(
+                    container.state.as_str(),
+                    "running" | "paused" | "restarting"
+                ),
+            })
+            .collect())
+    }
+
+    async fn list_dangling_images(&self) -> Result<Vec<ImageSummary>, SandboxError> {
+        // only images built by this crate carry the context hash label
+        let options = ImageListOptions::builder()
+            .filter(vec![
+                ImageFilter::Dangling,
+                ImageFilter::LabelName(CONTEXT_HASH_LABEL.to_owned()),
+            ])
+            .build();
+        let images = self
+            .docker
+            .images()
+            .list(&options)
+            .await
+            .map_err(SandboxError::ListImages)?;
+        Ok(images
+            .into_iter()
+            .map(|image| ImageSummary {
+                id: image.id,
+                created: image.created.into(),
+            })
+            .collect())
+    }
+
+    async fn remove_image(&self, image_id: &str) -> Result<(), SandboxError> {
+        self.docker
+            .images()
+            .get(image_id)
+            .delete()
+            .await
+            .map(|_| ())
+            .map_err(|err| SandboxError::RemoveImage {
+                image_id: image_id.to_owned(),
+                source: err,
+            })
+    }
+
+    async fn create_container(&self, spec: &ContainerSpec<'_>) -> Result<String, SandboxError> {
+        let slice_cmd: Vec<&str> = spec.cmd.iter().map(String::as_str).collect();
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        let labels = HashMap::from([
+            (OWNER_LABEL, OWNER),
+            (SANDBOX_ID_LABEL, spec.sandbox_id),
+            (CREATED_LABEL, created.as_str()),
+        ]);
+        let mut builder = ContainerOptions::builder(spec.image_tag);
+        builder
+            .labels(&labels)
+            .working_dir(&spec.workdir.to_string_lossy())
+            .attach_stdin(true)
+            .attach_stdout(true)
+            .attach_stderr(true)
+            .tty(false)
+            .cmd(slice_cmd);
+        let mount = format!("{}:{}", spec.host_dir.display(), spec.workdir.display());
+        if spec.transfer == TransferMode::BindMount {
+            builder.volumes(vec![&mount]);
+        }
+        if spec.network == NetworkPolicy::Disabled {
+            builder.network_mode("none");
+        }
+        if let Some(bytes) = spec.limits.memory_bytes {
+            // swap is capped at the same value so the limit can't be dodged by swapping
+            builder.memory(bytes).memory_swap(bytes as i64);
+        }
+        if let Some(cpus) = spec.limits.cpus {
+            builder.cpus(cpus);
+        }
+        let options = builder.build();
+        self.docker.containers().create(&options).await.map_or_else(
+            |err| {
+                Err(SandboxError::CreateContainer {
+                    image_tag: spec.image_tag.to_owned(),
+                    source: err,
+                })
+            },
+            |result| Ok(result.id),
+        )
+    }
+
+    async fn attach_container<'a>(
+        &'a self,
+        container_id: &str,
+    ) -> Result<AttachedContainer<'a>, SandboxError> {
+        let (reader, writer) = self
+            .docker
+            .containers()
+            .get(container_id)
+            .attach()
+            .await
+            .map_err(|err| SandboxError::AtachToContainer {
+                container_id: container_id.to_owned(),
+                source: err,
+            })?
+            .split();
+        let id = container_id.to_owned();
+        let output = reader
+            .map_err(move |err| SandboxError::ReadOutput {
+                container_id: id.to_owned(),
+                source: err,
+            })
+            .boxed();
+        Ok(AttachedContainer {
+            output,
+            stdin: Box::pin(writer),
+        })
+    }
+
+    async fn copy_into_container(
+        &self,
+        container_id: &str,
+        archive: Vec<u8>,
+    ) -> Result<(), SandboxError> {
+        self.docker
+            .containers()
+            .get(container_id)
+            .copy_to(Path::new("/"), archive.into())
+            .await
+            .map_err(|err| SandboxError::CopyToContainer {
+                container_id: container_id.to_owned(),
+                source: err,
+            })
+    }
+
+    async fn copy_from_container(
+        &self,
+        container_id: &str,
+        path: &Path,
+        dest: &Path,
+    ) -> Result<(), SandboxError> {
+        let write_err = |err| SandboxError::WriteArchive {
+            path: dest.to_owned(),
+            source: err,
+        };
+        // the archive is spooled to disk so a chatty program can't exhaust our memory
+        let mut file = File::create(dest).map_err(write_err)?;
+        let containers = self.docker.containers();
+        let container = containers.get(container_id);
+        let mut chunks = container.copy_from(path).boxed();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|err| SandboxError::CopyFromContainer {
+                container_id: container_id.to_owned(),
+                source: err,
+            })?;
+            file.write_all(&chunk).map_err(write_err)?;
+        }
+        Ok(())
+    }
+
+    async fn start_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        self.docker
+            .containers()
+            .get(container_id)
+            .start()
+            .await
+            .map_err(|err| SandboxError::StartContainer {
+                container_id: container_id.to_owned(),
+                source: err,
+            })
+    }
+
+    async fn wait_container(&self, container_id: &str) -> Result<ContainerExit, SandboxError> {
+        let container = self.docker.containers().get(container_id);
+        let wait = container.wait();
+        tokio::pin!(wait);
+        let mut usage = ResourceUsage::default();
+        // usage is sampled from the cgroup while the container runs and once more when it exits;
+        // looking the cgroup up by name needs no round trip to the daemon, so even a short run is
+        // sampled before it ends. a daemon on another host, or a cgroup layout not known here,
+        // leaves the fields unset
+        let cgroup = match ContainerCgroup::by_id(container_id) {
+            Some(cgroup) => Some(cgroup),
+            None => match container.inspect().await {
+                Ok(details) => ContainerCgroup::find(details.state.pid, container_id),
+                Err(_) => None,
+            },
+        };
+        let mut samples = time::interval(STATS_INTERVAL);
+        let exit = loop {
+            tokio::select! {
+                exit = &mut wait => break exit,
+                _ = samples.tick(), if cgroup.is_some() => {
+                    if let Some(cgroup) = &cgroup {
+                        cgroup.sample(&mut usage);
+                    }
+                }
+            }
+        }
+        .map_err(|err| SandboxError::WaitContainer {
+            container_id: container_id.to_owned(),
+            source: err,
+        })?;
+        if let Some(cgroup) = &cgroup {
+            cgroup.sample(&mut usage);
+        }
+        let mut oom_killed = false;
+        if let Ok(details) = container.inspect().await {
+            usage.wall_time = (details.state.finished_at - details.state.started_at)
+                .to_std()
+                .ok();
+            oom_killed = details.state.oom_killed;
+        }
+        Ok(ContainerExit {
+            exit_code: exit.status_code,
+            oom_killed,
+            usage,
+        })
+    }
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        self.docker
+            .containers()
+            .get(container_id)
+            .kill(None)
+            .await
+            .map_err(|err| SandboxError::KillContainer {
+                container_id: container_id.to_owned(),
+                source: err,
+            })
+    }
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        let options = RmContainerOptions::builder()
+            .volumes(true)
+            .force(true)
+            .build();
+        self.docker
+            .containers()
+            .get(container_id)
+            .remove(options)
+            .await
+            .map_err(|err| SandboxError::RemoveContainer {
+                container_id: container_id.to_owned(),
+                source: err,
+            })
+    }
+}
+
+impl fmt::Display for DockerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerEndpoint::Default => match env::var("DOCKER_HOST") {
+                Ok(host) => write!(f, "{host}"),
+                Err(_) => write!(f, "unix:///var/run/docker.sock"),
+            },
+            DockerEndpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            DockerEndpoint::Tcp { url, .. } => write!(f, "{url}"),
+        }
+    }
+}
+
+fn connect(endpoint: &DockerEndpoint) -> Result<(Docker, Option<Tunnel>), SandboxError> {
+    match endpoint {
+        DockerEndpoint::Default => Ok((Docker::new(), None)),
+        DockerEndpoint::Unix(path) => Ok((Docker::unix(path.display().to_string()), None)),
+        DockerEndpoint::Tcp { url, tls } => {
+            let scheme = if tls.is_some() { "https" } else { "http" };
+            let normalized = match url.strip_prefix("tcp://") {
+                Some(rest) => format!("{scheme}://{rest}"),
+                None => url.to_owned(),
+            };
+            let uri = normalized
+                .parse::<Uri>()
+                .ok()
+                .filter(|uri| uri.host().is_some() && uri.scheme_str().is_some())
+                .ok_or_else(|| SandboxError::InvalidEndpoint {
+                    url: url.to_owned(),
+                })?;
+            // shiplift would pick up certificates left in the environment for any tcp host
+            let tunnel = match tls {
+                Some(tls) => connect_tls(&uri, tls)?,
+                None => open_tunnel(&uri, None)?,
+            };
+            let docker = Docker::unix(tunnel.socket().display().to_string());
+            Ok((docker, Some(tunnel)))
+        }
+    }
+}
+
+fn daemon_socket(endpoint: &DockerEndpoint, tunnel: Option<&Tunnel>) -> Option<PathBuf> {
+    match endpoint {
+        DockerEndpoint::Default => match env::var("DOCKER_HOST") {
+            Ok(host) => host.strip_prefix("unix://").map(PathBuf::from),
+            Err(_) => Some("/var/run/docker.sock".into()),
+        },
+        DockerEndpoint::Unix(path) => Some(path.to_owned()),
+        DockerEndpoint::Tcp { .. } => tunnel.map(Tunnel::socket),
+    }
+}
+
+// the daemon answers with the same stream of json messages a build does; a refused request is
+// turned into one of them so that both kinds of failure are reported alike
+async fn post_archive(
+    socket: &Path,
+    tarball: tokio::fs::File,
+) -> Result<Vec<Value>, shiplift::Error> {
+    let client = hyper::Client::builder().build::<_, Body>(UnixConnector);
+    let request = Request::post(hyperlocal::Uri::new(socket, "/images/load"))
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .body(Body::wrap_stream(ReaderStream::new(tarball)))?;
+    let response = client.request(request).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        let message = serde_json::from_slice::<Value>(&body)
+            .ok()
+            .and_then(|value| Some(value.get("message")?.as_str()?.to_owned()))
+            .unwrap_or_else(|| format!("daemon answered {status}"));
+        return Ok(vec![serde_json::json!({ "error": message })]);
+    }
+    Ok(serde_json::Deserializer::from_slice(&body)
+        .into_iter::<Value>()
+        .filter_map(Result::ok)
+        .collect())
+}
+
+fn connect_tls(uri: &Uri, tls: &TlsConfig) -> Result<Tunnel, SandboxError> {
+    for file in ["cert.pem", "key.pem", "ca.pem"] {
+        if !tls.cert_dir.join(file).is_file() {
+            return Err(SandboxError::MissingTlsFile {
+                cert_dir: tls.cert_dir.to_path_buf(),
+                file: file.to_owned(),
+            });
+        }
+    }
+    open_tunnel(uri, Some(tls))
+}
+
+fn open_tunnel(uri: &Uri, tls: Option<&TlsConfig>) -> Result<Tunnel, SandboxError> {
+    let host = uri.host().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let default_port = if tls.is_some() { 2376 } else { 2375 };
+    Tunnel::open(host, uri.port_u16().unwrap_or(default_port), tls)
+}
+
+const BUILD_LOG_TAIL_LEN: usize = 20;
+
+const STATS_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct BuildLog {
+    step: Option<String>,
+    tail: VecDeque<String>,
+}
+
+impl BuildLog {
+    fn push(&mut self, message: &Value) -> Vec<String> {
+        let text = message
+            .get("stream")
+            .or_else(|| message.get("status"))
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let lines = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        for line in &lines {
+            if line.starts_with("Step ") || line.starts_with("STEP ") {
+                self.step = Some(line.to_owned());
+            }
+            if self.tail.len() == BUILD_LOG_TAIL_LEN {
+                self.tail.pop_front();
+            }
+            self.tail.push_back(line.to_owned());
+        }
+        lines
+    }
+
+    fn into_error(self, message: String) -> SandboxError {
+        SandboxError::BuildImage {
+            step: self.step,
+            message,
+            log_tail: self.tail.into(),
+        }
+    }
+}
+
+fn build_error(message: &Value) -> Option<String> {
+    message
+        .get("errorDetail")
+        .and_then(|detail| detail.get("message"))
+        .or_else(|| message.get("error"))
+        .and_then(Value::as_str)
+        .map(|error| error.trim_end().to_owned())
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use futures::stream;
+use futures::StreamExt;
+use shiplift::tty::TtyChunk;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::unix::pipe;
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+use super::{AttachedContainer, ContainerBackend, ContainerExit, ContainerSpec, DaemonVersion};
+use super::{ContainerSummary, ImageSummary, ResourceLimits, ResourceUsage};
+use super::{OWNER, OWNER_LABEL, SANDBOX_ID_LABEL};
+use crate::sandbox::{BuildProgress, SandboxError};
+
+pub struct LocalBackend {
+    isolation: Isolation,
+    limits: LocalLimits,
+    binds: Vec<PathBuf>,
+    processes: Mutex<HashMap<String, LocalProcess>>,
+    next_id: AtomicU64,
+}
+
+// unshare only separates namespaces, the program still sees and can write to everything the
+// user running the sandbox can, so it is never picked unless asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Isolation {
+    Bubblewrap,
+    Unshare,
+}
+
+const ISOLATION_ENV: &str = "RUSTYCHAINS_ISOLATION";
+
+// what a toolchain needs from the host; everything else, home directories included, stays hidden
+const TOOLCHAIN_PATHS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/opt",
+    "/etc/alternatives",
+    "/etc/ld.so.cache",
+    "/etc/ld.so.conf",
+    "/etc/ld.so.conf.d",
+    "/etc/ssl/certs",
+    "/etc/java-*",
+    // nix profiles and dev shells, the project's own included, put interpreters in the store
+    "/nix/store",
+    "/run/current-system/sw",
+];
+
+// memory is limited through RLIMIT_DATA, which counts what a program allocates rather than the
+// address space it reserves up front the way the jvm does; max_processes is RLIMIT_NPROC, which
+// counts every process of the user running the sandbox, not only the sandboxed ones, so it has
+// to leave room for whatever else runs as that user
+#[derive(Clone, Debug)]
+pub struct LocalLimits {
+    pub memory_bytes: Option<u64>,
+    pub cpu_time: Option<Duration>,
+    pub max_processes: Option<u64>,
+    pub max_file_size: Option<u64>,
+    pub max_open_files: Option<u64>,
+}
+
+type OutputSender = UnboundedSender<Result<TtyChunk, SandboxError>>;
+
+struct LocalProcess {
+    sandbox_id: String,
+    created: SystemTime,
+    host_dir: PathBuf,
+    workdir: PathBuf,
+    cmd: Vec<String>,
+    limits: LocalLimits,
+    stdin: Option<io::PipeReader>,
+    output: Option<OutputSender>,
+    child: Option<Child>,
+    pid: Option<u32>,
+    started: Option<Instant>,
+}
+
+impl LocalBackend {
+    pub fn new(isolation: Isolation, limits: LocalLimits) -> Self {
+        LocalBackend {
+            isolation,
+            limits,
+            binds: Vec::new(),
+            processes: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    // toolchains outside the usual system paths, e.g. a pyenv or nvm install, have to be
+    // made visible explicitly; they are mounted read-only
+    pub fn bind<T>(mut self, path: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.binds.push(path.as_ref().to_owned());
+        self
+    }
+
+    fn command(&self, process: &LocalProcess) -> Command {
+        let host_dir = process.host_dir.display().to_string();
+        let workdir = process.workdir.display().to_string();
+        let mut command = Command::new(self.isolation.program());
+        match self.isolation {
+            Isolation::Bubblewrap => {
+                command.args(["--unshare-all", "--die-with-parent", "--new-session"]);
+                for path in self.toolchain_paths() {
+                    command.args(bind_args(&path));
+                }
+                command
+                    .args(["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"])
+                    .args(["--bind", &host_dir, &workdir, "--chdir", &workdir]);
+            }
+            Isolation::Unshare => {
+                command
+                    .args(["--user", "--map-root-user", "--net", "--ipc", "--uts"])
+                    .args(["--pid", "--fork", "--kill-child", "--mount", "--mount-proc"])
+                    .current_dir(&process.host_dir);
+            }
+        }
+        command.arg("--").args(&process.cmd).kill_on_drop(true);
+        apply_limits(&mut command, &process.limits);
+        command
+    }
+
+    fn toolchain_paths(&self) -> Vec<PathBuf> {
+        let defaults = TOOLCHAIN_PATHS
+            .iter()
+            .filter_map(|pattern| glob::glob(pattern).ok())
+            .flat_map(|paths| paths.filter_map(Result::ok));
+        defaults.chain(self.binds.iter().cloned()).collect()
+    }
+
+    // cpu shares have no rlimit counterpart, so only the memory limit carries over
+    fn limits_for(&self, limits: ResourceLimits) -> LocalLimits {
+        let memory_bytes = match (self.limits.memory_bytes, limits.memory_bytes) {
+            (Some(backend), Some(spec)) => Some(backend.min(spec)),
+            (backend, spec) => backend.or(spec),
+        };
+        LocalLimits {
+            memory_bytes,
+            ..self.limits.clone()
+        }
+    }
+
+    fn take_process<T, F>(&self, container_id: &str, take: F) -> Result<T, SandboxError>
+    where
+        F: FnOnce(&mut LocalProcess) -> Option<T>,
+    {
+        let mut processes = self.processes.lock().unwrap();
+        processes
+            .get_mut(container_id)
+            .and_then(take)
+            .ok_or_else(|| SandboxError::UnknownProcess(container_id.to_owned()))
+    }
+}
+
+impl Isolation {
+    pub fn detect() -> Result<Self, SandboxError> {
+        let has_bwrap = env::var_os("PATH")
+            .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join("bwrap").is_file()));
+        if has_bwrap {
+            Ok(Isolation::Bubblewrap)
+        } else {
+            Err(SandboxError::MissingBubblewrap)
+        }
+    }
+
+    // RUSTYCHAINS_ISOLATION=unshare is the only way to opt into running without bwrap
+    pub fn from_env() -> Result<Self, SandboxError> {
+        match env::var(ISOLATION_ENV).as_deref() {
+            Err(_) => Isolation::detect(),
+            Ok("bwrap") => Ok(Isolation::Bubblewrap),
+            Ok("unshare") => Ok(Isolation::Unshare),
+            Ok(other) => Err(SandboxError::UnknownIsolation(other.to_owned())),
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Isolation::Bubblewrap => "bwrap",
+            Isolation::Unshare => "unshare",
+        }
+    }
+}
+
+impl Default for LocalLimits {
+    fn default() -> Self {
+        LocalLimits {
+            memory_bytes: None,
+            cpu_time: None,
+            max_processes: None,
+            max_file_size: Some(64 * 1024 * 1024),
+            max_open_files: Some(256),
+        }
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for LocalBackend {
+    fn endpoint(&self) -> String {
+        format!("local ({})", self.isolation.program())
+    }
+
+    async fn ping(&self) -> Result<(), SandboxError> {
+        self.version().await?;
+        Ok(())
+    }
+
+    async fn version(&self) -> Result<DaemonVersion, SandboxError> {
+        let program = self.isolation.program();
+        let output = Command::new(program)
+            .arg("--version")
+            .output()
+            .await
+            .map_err(|err| SandboxError::SpawnProcess {
+                cmd: program.to_owned(),
+                source: err,
+            })?;
+        let version = String::from_utf8_lossy(&output.stdout);
+        Ok(DaemonVersion {
+            version: version.lines().next().unwrap_or_default().to_owned(),
+            api_version: String::new(),
+            os: env::consts::OS.to_owned(),
+            arch: env::consts::ARCH.to_owned(),
+        })
+    }
+
+    async fn image_labels(
+        &self,
+        _image_tag: &str,
+    ) -> Result<HashMap<String, String>, SandboxError> {
+        Ok(HashMap::new())
+    }
+
+    async fn build_image(
+        &self,
+        _context: &Path,
+        _image_tag: &str,
+        _progress: Option<&BuildProgress>,
+    ) -> Result<(), SandboxError> {
+        Ok(())
+    }
+
+    async fn load_image(&self, _archive: &Path) -> Result<(), SandboxError> {
+        Ok(())
+    }
+
+    // local processes die with the process that spawned them, so only the ones this backend
+    // still tracks are listed
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>, SandboxError> {
+        let processes = self.processes.lock().unwrap();
+        Ok(processes
+            .iter()
+            .map(|(id, process)| ContainerSummary {
+                id: id.to_owned(),
+                created: process.created,
+                labels: HashMap::from([
+                    (OWNER_LABEL.to_owned(), OWNER.to_owned()),
+                    (SANDBOX_ID_LABEL.to_owned(), process.sandbox_id.to_owned()),
+                ]),
+                // an exited process stays tracked until it is removed, which is as good as
+                // running as far as sweeping is concerned
+                running: process.started.is_some(),
+            })
+            .collect())
+    }
+
+    async fn list_dangling_images(&self) -> Result<Vec<ImageSummary>, SandboxError> {
+        Ok(Vec::new())
+    }
+
+    async fn remove_image(&self, _image_id: &str) -> Result<(), SandboxError> {
+        Ok(())
+    }
+
+    // both isolation modes unshare the network namespace, so the network policy is always
+    // treated as disabled
+    async fn create_container(&self, spec: &ContainerSpec<'_>) -> Result<String, SandboxError> {
+        let id = format!("local-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let process = LocalProcess {
+            sandbox_id: spec.sandbox_id.to_owned(),
+            created: SystemTime::now(),
+            host_dir: spec.host_dir.to_path_buf(),
+            workdir: spec.workdir.to_path_buf(),
+            cmd: spec.cmd.to_vec(),
+            limits: self.limits_for(spec.limits),
+            stdin: None,
+            output: None,
+            child: None,
+            pid: None,
+            started: None,
+        };
+        self.processes
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), process);
+        Ok(id)
+    }
+
+    async fn attach_container<'a>(
+        &'a self,
+        container_id: &str,
+    ) -> Result<AttachedContainer<'a>, SandboxError> {
+        let attach_err = |err| SandboxError::AttachToProcess {
+            container_id: container_id.to_owned(),
+            source: err,
+        };
+        let (reader, writer) = io::pipe().map_err(attach_err)?;
+        let writer = pipe::Sender::from_owned_fd(OwnedFd::from(writer)).map_err(attach_err)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.take_process(container_id, |process| {
+            process.stdin = Some(reader);
+            process.output = Some(sender);
+            Some(())
+        })?;
+
+        let output = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+        .boxed();
+        Ok(AttachedContainer {
+            output,
+            stdin: Box::pin(writer.compat_write()),
+        })
+    }
+
+    // processes run directly in the host directory, so there is nothing to transfer
+    async fn copy_into_container(
+        &self,
+        _container_id: &str,
+        _archive: Vec<u8>,
+    ) -> Result<(), SandboxError> {
+        Ok(())
+    }
+
+    async fn copy_from_container(
+        &self,
+        _container_id: &str,
+        _path: &Path,
+        dest: &Path,
+    ) -> Result<(), SandboxError> {
+        fs::File::create(dest)
+            .map(|_| ())
+            .map_err(|err| SandboxError::WriteArchive {
+                path: dest.to_owned(),
+                source: err,
+            })
+    }
+
+    async fn start_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        let mut processes = self.processes.lock().unwrap();
+        let process = processes
+            .get_mut(container_id)
+            .ok_or_else(|| SandboxError::UnknownProcess(container_id.to_owned()))?;
+        let mut command = self.command(process);
+        command
+            .stdin(process.stdin.take().map_or_else(Stdio::null, Stdio::from))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn().map_err(|err| SandboxError::SpawnProcess {
+            cmd: process.cmd.join(" "),
+            source: err,
+        })?;
+        if let Some(sender) = process.output.take() {
+            if let Some(stdout) = child.stdout.take() {
+                forward_output(stdout, TtyChunk::StdOut, sender.clone(), container_id);
+            }
+            if let Some(stderr) = child.stderr.take() {
+                forward_output(stderr, TtyChunk::StdErr, sender, container_id);
+            }
+        }
+        process.pid = child.id();
+        process.child = Some(child);
+        process.started = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn wait_container(&self, container_id: &str) -> Result<ContainerExit, SandboxError> {
+        let (mut child, started) = self.take_process(container_id, |process| {
+            process.child.take().zip(process.started)
+        })?;
+        let wait_err = |err| SandboxError::WaitProcess {
+            container_id: container_id.to_owned(),
+            source: err,
+        };
+        let mut usage = ResourceUsage::default();
+        if let Some(pid) = child.id() {
+            // wait for the exit without reaping so the zombie's cpu times can
+            // still be read from procfs
+            tokio::task::spawn_blocking(move || wait_exited(pid))
+                .await
+                .map_err(io::Error::other)
+                .and_then(|result| result)
+                .map_err(wait_err)?;
+            usage = process_usage(pid);
+        }
+        let status = child.wait().await.map_err(wait_err)?;
+        if let Some(process) = self.processes.lock().unwrap().get_mut(container_id) {
+            process.pid = None;
+        }
+        usage.wall_time = Some(started.elapsed());
+        Ok(ContainerExit {
+            exit_code: exit_code(status),
+            oom_killed: false,
+            usage,
+        })
+    }
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        // the child may already be owned by a pending wait, so it is signalled by pid; the
+        // isolation wrapper takes the sandboxed program down with it
+        let pid = {
+            let processes = self.processes.lock().unwrap();
+            let process = processes
+                .get(container_id)
+                .ok_or_else(|| SandboxError::UnknownProcess(container_id.to_owned()))?;
+            match process.pid {
+                Some(pid) => pid,
+                None => return Ok(()),
+            }
+        };
+        // SAFETY: kill has no memory safety preconditions
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(SandboxError::KillProcess {
+                    container_id: container_id.to_owned(),
+                    source: err,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        let process = self.processes.lock().unwrap().remove(container_id);
+        if let Some(mut child) = process.and_then(|process| process.child) {
+            let _ = child.kill().await;
+        }
+        Ok(())
+    }
+}
+
+// stdout and stderr come through separate pipes, so a transcript keeps the order within each
+// of them but writes to both in quick succession may be recorded in either order; docker's
+// attach stream carries both in the order they were written
+fn forward_output<R>(
+    mut reader: R,
+    chunk: fn(Vec<u8>) -> TtyChunk,
+    sender: OutputSender,
+    container_id: &str,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let container_id = container_id.to_owned();
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 8192];
+        loop {
+            let item = match reader.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => Ok(chunk(buffer[..n].to_vec())),
+                Err(err) => Err(SandboxError::ReadProcessOutput {
+                    container_id: container_id.to_owned(),
+                    source: err,
+                }),
+            };
+            let failed = item.is_err();
+            if sender.send(item).is_err() || failed {
+                break;
+            }
+        }
+    });
+}
+
+fn wait_exited(pid: u32) -> io::Result<()> {
+    loop {
+        // SAFETY: siginfo_t is plain data and waitid only writes into it
+        let mut info = unsafe { std::mem::zeroed::<libc::siginfo_t>() };
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        // SAFETY: info points to a valid siginfo_t for the duration of the call
+        if unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+// utime and stime of the isolation wrapper plus the times of every child it
+// reaped, which covers the sandboxed program
+fn process_usage(pid: u32) -> ResourceUsage {
+    let Ok(stat) = fs::read_to_string(format!("/proc/{pid}/stat")) else {
+        return ResourceUsage::default();
+    };
+    // the command name may contain spaces, so fields are counted after it
+    let fields = stat
+        .rsplit_once(')')
+        .map(|(_, rest)| rest.split_whitespace().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let ticks = |idx: usize| fields.get(idx).and_then(|field| field.parse::<u64>().ok());
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    let cpu_time = |own, children| {
+        let ticks = ticks(own)? + ticks(children)?;
+        Some(Duration::from_millis(ticks * 1000 / ticks_per_sec))
+    };
+    ResourceUsage {
+        cpu_user: cpu_time(11, 13),
+        cpu_system: cpu_time(12, 14),
+        ..ResourceUsage::default()
+    }
+}
+
+// merged /usr systems link /bin and friends into /usr, which bwrap has to recreate as links
+fn bind_args(path: &Path) -> Vec<String> {
+    let path_str = path.display().to_string();
+    match fs::read_link(path) {
+        Ok(target) => vec![
+            "--symlink".to_owned(),
+            target.display().to_string(),
+            path_str,
+        ],
+        Err(_) => vec!["--ro-bind-try".to_owned(), path_str.to_owned(), path_str],
+    }
+}
+
+fn exit_code(status: std::process::ExitStatus) -> u64 {
+    use std::os::unix::process::ExitStatusExt;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code as u64,
+        (None, Some(signal)) => 128 + signal as u64,
+        (None, None) => 1,
+    }
+}
+
+fn apply_limits(command: &mut Command, limits: &LocalLimits) {
+    let limits = limits.clone();
+    // SAFETY: the closure only calls setrlimit, which is async-signal-safe
+    unsafe {
+        command.pre_exec(move || set_limits(&limits));
+    }
+}
+
+fn set_limits(limits: &LocalLimits) -> io::Result<()> {
+    if let Some(bytes) = limits.memory_bytes {
+        set_limit(libc::RLIMIT_DATA, bytes)?;
+    }
+    if let Some(cpu_time) = limits.cpu_time {
+        set_limit(libc::RLIMIT_CPU, cpu_time.as_secs().max(1))?;
+    }
+    if let Some(processes) = limits.max_processes {
+        set_limit(libc::RLIMIT_NPROC, processes)?;
+    }
+    if let Some(bytes) = limits.max_file_size {
+        set_limit(libc::RLIMIT_FSIZE, bytes)?;
+    }
+    if let Some(files) = limits.max_open_files {
+        set_limit(libc::RLIMIT_NOFILE, files)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+fn set_limit(resource: Resource, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit that outlives the call
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::os::unix::net;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::thread;
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod};
+use tempdir::TempDir;
+use tokio::net::{TcpStream, UnixListener, UnixStream};
+use tokio::runtime;
+use tokio::sync::oneshot;
+use tokio_openssl::SslStream;
+
+use super::TlsConfig;
+use crate::sandbox::SandboxError;
+
+const SOCKET_FILE: &str = "docker.sock";
+
+// shiplift can only be given certificates through the process environment and unwraps whatever
+// it fails to load, so tcp daemons are reached through a unix socket that forwards every
+// connection, wrapped in tls by a connector built here; it runs on its own thread so that
+// connecting does not depend on being inside a runtime, and stops with the backend
+pub(crate) struct Tunnel {
+    dir: TempDir,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl Tunnel {
+    pub(crate) fn open(
+        host: &str,
+        port: u16,
+        tls: Option<&TlsConfig>,
+    ) -> Result<Self, SandboxError> {
+        let connector = tls
+            .map(|tls| {
+                connector(tls).map_err(|err| SandboxError::InvalidTlsFiles {
+                    cert_dir: tls.cert_dir.to_path_buf(),
+                    source: err,
+                })
+            })
+            .transpose()?;
+        let dir = TempDir::new("rustychains-tunnel").map_err(SandboxError::OpenTunnel)?;
+        let listener = net::UnixListener::bind(dir.path().join(SOCKET_FILE))
+            .map_err(SandboxError::OpenTunnel)?;
+        listener
+            .set_nonblocking(true)
+            .map_err(SandboxError::OpenTunnel)?;
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(SandboxError::OpenTunnel)?;
+        let (shutdown, stopped) = oneshot::channel();
+        let host = host.to_owned();
+        thread::Builder::new()
+            .name("rustychains-tunnel".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let Ok(listener) = UnixListener::from_std(listener) else {
+                        return;
+                    };
+                    tokio::select! {
+                        _ = stopped => {}
+                        () = accept(listener, host, port, connector) => {}
+                    }
+                })
+            })
+            .map_err(SandboxError::OpenTunnel)?;
+        Ok(Tunnel {
+            dir,
+            _shutdown: shutdown,
+        })
+    }
+
+    pub(crate) fn socket(&self) -> PathBuf {
+        self.dir.path().join(SOCKET_FILE)
+    }
+}
+
+// the daemon is always verified against ca.pem, and its certificate has to name the host
+fn connector(tls: &TlsConfig) -> Result<SslConnector, ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    builder.set_certificate_file(tls.cert_dir.join("cert.pem"), SslFiletype::PEM)?;
+    builder.set_private_key_file(tls.cert_dir.join("key.pem"), SslFiletype::PEM)?;
+    builder.check_private_key()?;
+    builder.set_ca_file(tls.cert_dir.join("ca.pem"))?;
+    Ok(builder.build())
+}
+
+async fn accept(listener: UnixListener, host: String, port: u16, connector: Option<SslConnector>) {
+    while let Ok((client, _)) = listener.accept().await {
+        let host = host.clone();
+        let connector = connector.clone();
+        // a failed connection is dropped, which the client reports as an unreachable daemon
+        tokio::spawn(async move {
+            let _ = forward(client, &host, port, connector.as_ref()).await;
+        });
+    }
+}
+
+async fn forward(
+    mut client: UnixStream,
+    host: &str,
+    port: u16,
+    connector: Option<&SslConnector>,
+) -> io::Result<()> {
+    let mut daemon = TcpStream::connect((host, port)).await?;
+    let Some(connector) = connector else {
+        tokio::io::copy_bidirectional(&mut client, &mut daemon).await?;
+        return Ok(());
+    };
+    let ssl = connector
+        .configure()
+        .and_then(|config| config.into_ssl(host))
+        .map_err(io::Error::other)?;
+    let mut daemon = SslStream::new(ssl, daemon).map_err(io::Error::other)?;
+    Pin::new(&mut daemon)
+        .connect()
+        .await
+        .map_err(io::Error::other)?;
+    tokio::io::copy_bidirectional(&mut client, &mut daemon).await?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tempdir::TempDir;
+
+use crate::backend::{ContainerBackend, SANDBOX_ID_LABEL};
+use crate::sandbox::SandboxError;
+
+pub const TEMP_DIR_PREFIX: &str = "rustychains";
+// written into every temp dir the crate creates, naming the host and pid of its owner; nothing
+// without it is ever swept, whatever its name
+pub const OWNER_FILE: &str = "owner";
+pub const DEFAULT_STALE_AGE: Duration = Duration::from_secs(60 * 60);
+
+// a sweep is best-effort: whatever cannot be removed is counted as failed and left for the next one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub containers: usize,
+    pub images: usize,
+    pub temp_dirs: usize,
+    pub failed: usize,
+}
+
+static NEXT_SANDBOX: AtomicU64 = AtomicU64::new(0);
+
+// sandbox ids and temp dirs name the host and process that made them, so that a sweep can tell
+// whether their owner is still around
+pub(crate) fn new_sandbox_id() -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let seq = NEXT_SANDBOX.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{:x}-{started:x}-{seq}", hostname(), process::id())
+}
+
+// the owner marker sits next to the directory handed out, so that programs never see it in
+// their working directory
+pub(crate) struct WorkDir {
+    _root: TempDir,
+    work: PathBuf,
+}
+
+impl WorkDir {
+    pub(crate) fn new() -> Result<Self, SandboxError> {
+        let root = TempDir::new(&format!("{TEMP_DIR_PREFIX}-{:x}", process::id()))
+            .map_err(SandboxError::CreateTempDirectory)?;
+        let owner = format!("{}\n{}\n", hostname(), process::id());
+        fs::write(root.path().join(OWNER_FILE), owner)
+            .map_err(SandboxError::CreateTempDirectory)?;
+        let work = root.path().join("work");
+        fs::create_dir(&work).map_err(SandboxError::CreateTempDirectory)?;
+        Ok(WorkDir { _root: root, work })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.work
+    }
+}
+
+impl AsRef<Path> for WorkDir {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+pub(crate) async fn cleanup_stale(
+    backend: &dyn ContainerBackend,
+    sandbox_id: &str,
+    max_age: Duration,
+) -> Result<CleanupReport, SandboxError> {
+    let mut report = CleanupReport::default();
+    for container in backend.list_containers().await? {
+        let owner = container.labels.get(SANDBOX_ID_LABEL).map(String::as_str);
+        // containers of the sweeping sandbox may belong to long running sessions, and a running
+        // container is only abandoned once the process that started it is gone
+        let own = owner == Some(sandbox_id);
+        let abandoned = !container.running || owner.is_some_and(|owner| !owner_alive(owner));
+        if own || !abandoned || !is_stale(container.created, max_age) {
+            continue;
+        }
+        match backend.remove_container(&container.id).await {
+            Ok(()) => report.containers += 1,
+            Err(_) => report.failed += 1,
+        }
+    }
+    for image in backend.list_dangling_images().await? {
+        if !is_stale(image.created, max_age) {
+            continue;
+        }
+        // images still used by a container cannot be removed yet
+        match backend.remove_image(&image.id).await {
+            Ok(()) => report.images += 1,
+            Err(_) => report.failed += 1,
+        }
+    }
+    let (removed, failed) = remove_stale_temp_dirs(&env::temp_dir(), max_age)?;
+    report.temp_dirs = removed;
+    report.failed += failed;
+    Ok(report)
+}
+
+// a sandbox id that cannot be parsed, or that comes from another host sharing the daemon, is
+// assumed to be alive
+fn owner_alive(sandbox_id: &str) -> bool {
+    let mut parts = sandbox_id.rsplitn(4, '-');
+    let (Some(_), Some(_), Some(pid), Some(host)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return true;
+    };
+    match u32::from_str_radix(pid, 16) {
+        Ok(pid) if host == hostname() => process_alive(pid),
+        _ => true,
+    }
+}
+
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return String::new();
+    }
+    let len = name
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+// only directories marked by a process of this host that is gone are removed, however old they are
+fn remove_stale_temp_dirs(dir: &Path, max_age: Duration) -> Result<(usize, usize), SandboxError> {
+    let read_err = |err| SandboxError::ReadTempDirectory {
+        path: dir.to_owned(),
+        source: err,
+    };
+    let prefix = format!("{TEMP_DIR_PREFIX}-");
+    let (mut removed, mut failed) = (0, 0);
+    for entry in fs::read_dir(dir).map_err(read_err)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_dir() || marked_owner(&entry.path()).is_none_or(process_alive) {
+            continue;
+        }
+        let stale = metadata
+            .modified()
+            .is_ok_and(|modified| is_stale(modified, max_age));
+        if !stale {
+            continue;
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => removed += 1,
+            // another sweeper got there first, or the directory belongs to another user
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                ) => {}
+            Err(_) => failed += 1,
+        }
+    }
+    Ok((removed, failed))
+}
+
+fn marked_owner(dir: &Path) -> Option<u32> {
+    let marker = dir.join(OWNER_FILE);
+    if !fs::symlink_metadata(&marker).ok()?.is_file() {
+        return None;
+    }
+    let owner = fs::read_to_string(marker).ok()?;
+    let mut lines = owner.lines();
+    let (host, pid) = (lines.next()?, lines.next()?);
+    let pid = pid.parse().ok()?;
+    // a temp dir shared with another host says nothing about processes on this one
+    (host == hostname() && lines.next().is_none()).then_some(pid)
+}
+
+fn is_stale(created: SystemTime, max_age: Duration) -> bool {
+    created.elapsed().is_ok_and(|age| age >= max_age)
+}
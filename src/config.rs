@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::cleanup::DEFAULT_STALE_AGE;
+use crate::sandbox::{BackendKind, DockerEndpoint, Language, LanguageSpec, TlsConfig};
+use crate::sandbox::{NetworkPolicy, ResourceLimits, TransferMode, SANDBOX_DIR};
+
+pub const CONFIG_ENV: &str = "RUSTYCHAINS_CONFIG";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxConfig {
+    pub image: ImageConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    #[serde(default)]
+    pub network: NetworkPolicy,
+    #[serde(default)]
+    pub transfer: TransferMode,
+    #[serde(default = "default_sandbox_dir")]
+    pub sandbox_dir: PathBuf,
+    #[serde(default)]
+    pub languages: HashMap<Language, LanguageSpec>,
+    #[serde(default)]
+    pub versions: HashMap<Language, HashMap<String, LanguageVersionConfig>>,
+    #[serde(default)]
+    pub cleanup: CleanupConfig,
+}
+
+// without a directory the image is expected to exist already, or to be
+// loaded from the archive
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageConfig {
+    pub tag: String,
+    pub directory: Option<PathBuf>,
+    pub archive: Option<PathBuf>,
+    #[serde(default)]
+    pub languages: HashMap<Language, LanguageImageConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageImageConfig {
+    pub tag: String,
+    pub directory: Option<PathBuf>,
+}
+
+// a version needs its own image, its own commands or both
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageVersionConfig {
+    pub tag: Option<String>,
+    pub spec: Option<LanguageSpec>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub backend: Option<BackendKind>,
+    pub endpoint: Option<String>,
+    pub tls_cert_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleanupConfig {
+    pub on_build: bool,
+    pub max_age_secs: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read sandbox config at {path:?}")]
+    Read {
+        path: PathBuf,
+
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("invalid sandbox config in {path:?}")]
+    ParseFile {
+        path: PathBuf,
+
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("invalid sandbox config")]
+    Parse(#[source] toml::de::Error),
+
+    #[error("environment variable {0} is not set")]
+    MissingEnv(&'static str),
+
+    #[error("invalid sandbox config value for {key}: {message}")]
+    Invalid { key: String, message: String },
+}
+
+impl SandboxConfig {
+    // relative paths in a config file are relative to the file, not to wherever the program
+    // happens to be started from
+    pub fn load<T>(path: T) -> Result<Self, ConfigError>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| ConfigError::Read {
+            path: path.to_owned(),
+            source: err,
+        })?;
+        let mut config =
+            toml::from_str::<SandboxConfig>(&text).map_err(|err| ConfigError::ParseFile {
+                path: path.to_owned(),
+                source: err,
+            })?;
+        config.resolve_paths(path.parent().unwrap_or(Path::new("")));
+        config.validate()?;
+        Ok(config)
+    }
+
+    // paths in a config given as text stay relative to the working directory
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config = toml::from_str::<SandboxConfig>(text).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // the config file is named by RUSTYCHAINS_CONFIG, single keys are not read from the environment
+    pub fn load_from_env_path() -> Result<Self, ConfigError> {
+        let path = env::var_os(CONFIG_ENV).ok_or(ConfigError::MissingEnv(CONFIG_ENV))?;
+        Self::load(path)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.image.tag.trim().is_empty() {
+            return Err(invalid("image.tag", "must not be empty"));
+        }
+        if self.image.directory.is_some() && self.image.archive.is_some() {
+            return Err(invalid(
+                "image.archive",
+                "cannot be combined with image.directory",
+            ));
+        }
+        for (lang, image) in &self.image.languages {
+            if image.tag.trim().is_empty() {
+                return Err(invalid(
+                    &format!("image.languages.{}.tag", language_key(*lang)),
+                    "must not be empty",
+                ));
+            }
+        }
+        self.daemon.validate()?;
+        if self.limits.memory_bytes == Some(0) {
+            return Err(invalid("limits.memory_bytes", "must be greater than zero"));
+        }
+        if let Some(cpus) = self.limits.cpus {
+            if !cpus.is_finite() || cpus <= 0.0 {
+                return Err(invalid("limits.cpus", "must be a positive number"));
+            }
+        }
+        if !self.sandbox_dir.is_absolute() {
+            return Err(invalid("sandbox_dir", "must be an absolute path"));
+        }
+        for (lang, spec) in &self.languages {
+            validate_spec(&format!("languages.{}", language_key(*lang)), spec)?;
+        }
+        for (lang, versions) in &self.versions {
+            for (version, runtime) in versions {
+                let key = format!("versions.{}.{version:?}", language_key(*lang));
+                if runtime.tag.is_none() && runtime.spec.is_none() {
+                    return Err(invalid(&key, "must set a tag, a spec or both"));
+                }
+                if runtime
+                    .tag
+                    .as_ref()
+                    .is_some_and(|tag| tag.trim().is_empty())
+                {
+                    return Err(invalid(&format!("{key}.tag"), "must not be empty"));
+                }
+                if let Some(spec) = &runtime.spec {
+                    validate_spec(&format!("{key}.spec"), spec)?;
+                }
+            }
+        }
+        if self.cleanup.max_age_secs == 0 {
+            return Err(invalid("cleanup.max_age_secs", "must be greater than zero"));
+        }
+        Ok(())
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(path) = path {
+                *path = base.join(&*path);
+            }
+        };
+        resolve(&mut self.image.directory);
+        resolve(&mut self.image.archive);
+        for image in self.image.languages.values_mut() {
+            resolve(&mut image.directory);
+        }
+        resolve(&mut self.daemon.tls_cert_dir);
+    }
+}
+
+fn validate_spec(key: &str, spec: &LanguageSpec) -> Result<(), ConfigError> {
+    let extension = &spec.extension;
+    if extension.is_empty() || extension.starts_with('.') || extension.contains('/') {
+        return Err(invalid(
+            &format!("{key}.extension"),
+            "must be a bare file extension such as \"py\"",
+        ));
+    }
+    if spec.run.is_empty() {
+        return Err(invalid(&format!("{key}.run"), "must not be empty"));
+    }
+    Ok(())
+}
+
+impl DaemonConfig {
+    pub fn docker_endpoint(&self) -> Result<Option<DockerEndpoint>, ConfigError> {
+        let Some(endpoint) = &self.endpoint else {
+            if self.tls_cert_dir.is_some() {
+                return Err(invalid("daemon.tls_cert_dir", "requires a tcp endpoint"));
+            }
+            return Ok(None);
+        };
+        if let Some(path) = endpoint.strip_prefix("unix://") {
+            if self.tls_cert_dir.is_some() {
+                return Err(invalid("daemon.tls_cert_dir", "requires a tcp endpoint"));
+            }
+            return Ok(Some(DockerEndpoint::Unix(PathBuf::from(path))));
+        }
+        let is_tcp = ["tcp://", "http://", "https://"]
+            .iter()
+            .any(|scheme| endpoint.starts_with(scheme));
+        if !is_tcp {
+            return Err(invalid(
+                "daemon.endpoint",
+                "must start with unix://, tcp://, http:// or https://",
+            ));
+        }
+        let tls = self.tls_cert_dir.as_ref().map(|cert_dir| TlsConfig {
+            cert_dir: cert_dir.to_owned(),
+        });
+        Ok(Some(DockerEndpoint::Tcp {
+            url: endpoint.to_owned(),
+            tls,
+        }))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let endpoint = self.docker_endpoint()?;
+        match (self.backend, endpoint) {
+            (Some(BackendKind::Local), Some(_)) => Err(invalid(
+                "daemon.endpoint",
+                "is not supported by the local backend",
+            )),
+            (Some(BackendKind::Podman), Some(DockerEndpoint::Tcp { .. })) => Err(invalid(
+                "daemon.endpoint",
+                "must be a unix socket for the podman backend",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl CleanupConfig {
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
+    }
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        CleanupConfig {
+            on_build: true,
+            max_age_secs: DEFAULT_STALE_AGE.as_secs(),
+        }
+    }
+}
+
+fn default_sandbox_dir() -> PathBuf {
+    PathBuf::from(SANDBOX_DIR)
+}
+
+fn language_key(lang: Language) -> &'static str {
+    match lang {
+        Language::Python => "python",
+        Language::JavaScript => "javascript",
+        Language::Java => "java",
+    }
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_owned(),
+        message: message.to_owned(),
+    }
+}
@@ -0,0 +1,611 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::time::{self, Instant};
+
+use crate::sandbox::{CompiledCode, DockerSandbox, Language, ResourceLimits, RunOutput};
+use crate::sandbox::{RunOptions, Sandbox, SandboxError, SessionOptions};
+use crate::session::Session;
+
+pub mod testdata;
+
+pub use testdata::{TestDataError, TestSuite};
+
+pub const INTERACTOR_INPUT_FILE: &str = "input.txt";
+pub const CHECKER_INPUT_FILE: &str = "input.txt";
+pub const CHECKER_OUTPUT_FILE: &str = "output.txt";
+pub const CHECKER_ANSWER_FILE: &str = "answer.txt";
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub lang: Language,
+    pub code_file: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    Accepted,
+    WrongAnswer,
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+    RuntimeError,
+    CompileError,
+    JudgeError,
+}
+
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub name: String,
+    pub input: String,
+    pub expected: String,
+    pub timeout: Duration,
+    pub points: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum Checker {
+    Exact,
+    IgnoreWhitespace,
+    FloatTolerance(f64),
+    Program(Program),
+}
+
+pub struct Judge<'a> {
+    sandbox: &'a dyn Sandbox,
+    checker: Checker,
+    checker_timeout: Duration,
+    compile_timeout: Duration,
+    parallelism: usize,
+    limits: ResourceLimits,
+}
+
+pub struct CaseResult {
+    pub name: String,
+    pub verdict: Verdict,
+    pub points: u32,
+    pub max_points: u32,
+    pub output: Option<RunOutput>,
+    pub checker_message: Option<String>,
+}
+
+pub struct JudgeReport {
+    cases: Vec<CaseResult>,
+    compile_error: Option<String>,
+}
+
+pub struct InteractiveJudge<'a> {
+    sandbox: &'a DockerSandbox,
+    interactor: Program,
+    time_limit: Duration,
+    limits: ResourceLimits,
+}
+
+pub struct InteractionResult {
+    pub verdict: Verdict,
+    pub interactor: Option<RunOutput>,
+    pub submission: Option<RunOutput>,
+    pub compile_error: Option<String>,
+}
+
+impl Program {
+    pub fn new<T>(lang: Language, code_file: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        Program {
+            lang,
+            code_file: code_file.as_ref().to_owned(),
+        }
+    }
+}
+
+impl TestCase {
+    pub fn new(name: &str, input: &str, expected: &str, timeout: Duration) -> Self {
+        TestCase {
+            name: name.to_owned(),
+            input: input.to_owned(),
+            expected: expected.to_owned(),
+            timeout,
+            points: 1,
+        }
+    }
+
+    pub fn points(mut self, points: u32) -> Self {
+        self.points = points;
+        self
+    }
+}
+
+impl<'a> Judge<'a> {
+    pub fn new(sandbox: &'a dyn Sandbox) -> Self {
+        Judge {
+            sandbox,
+            checker: Checker::Exact,
+            checker_timeout: Duration::from_secs(10),
+            compile_timeout: Duration::from_secs(30),
+            parallelism: 1,
+            limits: ResourceLimits {
+                memory_bytes: Some(256 * 1024 * 1024),
+                cpus: Some(1.0),
+            },
+        }
+    }
+
+    pub fn checker(mut self, checker: Checker) -> Self {
+        self.checker = checker;
+        self
+    }
+
+    pub fn checker_timeout(mut self, timeout: Duration) -> Self {
+        self.checker_timeout = timeout;
+        self
+    }
+
+    pub fn compile_timeout(mut self, timeout: Duration) -> Self {
+        self.compile_timeout = timeout;
+        self
+    }
+
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn judge_suite<T>(
+        &self,
+        code_file: T,
+        lang: Language,
+        suite: &TestSuite,
+    ) -> Result<JudgeReport, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        self.judge(code_file, lang, suite.cases()).await
+    }
+
+    pub async fn judge<T>(
+        &self,
+        code_file: T,
+        lang: Language,
+        cases: &[TestCase],
+    ) -> Result<JudgeReport, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        let compiled = self
+            .sandbox
+            .compile(code_file.as_ref(), lang, self.compile_timeout)
+            .await;
+        let compiled = match compiled {
+            Ok(compiled) => compiled,
+            Err(SandboxError::CompileFailed { stderr, .. }) => {
+                return Ok(JudgeReport::compile_failed(cases, stderr))
+            }
+            Err(SandboxError::Timeout { duration, .. }) => {
+                let message = format!("compilation did not finish within {duration:?}");
+                return Ok(JudgeReport::compile_failed(cases, message));
+            }
+            Err(err) => return Err(err),
+        };
+        let results = futures::stream::iter(cases)
+            .map(|case| self.judge_case(&compiled, case))
+            .buffered(self.parallelism)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut report = JudgeReport {
+            cases: Vec::new(),
+            compile_error: None,
+        };
+        for (case, result) in cases.iter().zip(results) {
+            match result {
+                Ok(case_result) => report.cases.push(case_result),
+                // sandboxes that cannot compile ahead of time only fail once the case runs
+                Err(SandboxError::CompileFailed { stderr, .. }) => {
+                    report.compile_error.get_or_insert(stderr);
+                    report
+                        .cases
+                        .push(CaseResult::failed(case, Verdict::CompileError));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(report)
+    }
+
+    async fn judge_case(
+        &self,
+        compiled: &CompiledCode,
+        case: &TestCase,
+    ) -> Result<CaseResult, SandboxError> {
+        let options = RunOptions::new(case.timeout)
+            .stdin(Some(&case.input))
+            .limits(self.limits);
+        let output = match self.sandbox.run_compiled(compiled, &options).await {
+            Ok(output) => output,
+            Err(err) => match failed_verdict(&err) {
+                Some(verdict) => return Ok(CaseResult::failed(case, verdict)),
+                None => return Err(err),
+            },
+        };
+        let (verdict, checker_message) = if output.oom_killed {
+            (Verdict::MemoryLimitExceeded, None)
+        } else if output.exit_code != 0 {
+            (Verdict::RuntimeError, None)
+        } else {
+            self.check(case, &output.stdout).await?
+        };
+        Ok(CaseResult {
+            name: case.name.to_owned(),
+            verdict,
+            points: if verdict == Verdict::Accepted {
+                case.points
+            } else {
+                0
+            },
+            max_points: case.points,
+            output: Some(output),
+            checker_message,
+        })
+    }
+
+    async fn check(
+        &self,
+        case: &TestCase,
+        output: &str,
+    ) -> Result<(Verdict, Option<String>), SandboxError> {
+        let accepted = match &self.checker {
+            Checker::Exact => output == case.expected,
+            Checker::IgnoreWhitespace => output
+                .split_whitespace()
+                .eq(case.expected.split_whitespace()),
+            Checker::FloatTolerance(tolerance) => floats_match(output, &case.expected, *tolerance),
+            Checker::Program(program) => return self.run_checker(program, case, output).await,
+        };
+        let verdict = if accepted {
+            Verdict::Accepted
+        } else {
+            Verdict::WrongAnswer
+        };
+        Ok((verdict, None))
+    }
+
+    // the checker gets the test input, the submission output and the expected answer as files
+    // and accepts by exiting with 0; anything it prints is kept as the message
+    async fn run_checker(
+        &self,
+        checker: &Program,
+        case: &TestCase,
+        output: &str,
+    ) -> Result<(Verdict, Option<String>), SandboxError> {
+        let options = RunOptions::new(self.checker_timeout)
+            .input_file(CHECKER_INPUT_FILE, case.input.as_bytes())
+            .input_file(CHECKER_OUTPUT_FILE, output.as_bytes())
+            .input_file(CHECKER_ANSWER_FILE, case.expected.as_bytes());
+        let checked = self
+            .sandbox
+            .run_code_with_options(&checker.code_file, checker.lang, &options)
+            .await?;
+        let verdict = match checked.exit_code {
+            0 => Verdict::Accepted,
+            1 => Verdict::WrongAnswer,
+            _ => Verdict::JudgeError,
+        };
+        let message = [checked.stdout.trim(), checked.stderr.trim()]
+            .into_iter()
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok((verdict, (!message.is_empty()).then_some(message)))
+    }
+}
+
+impl CaseResult {
+    fn failed(case: &TestCase, verdict: Verdict) -> Self {
+        CaseResult {
+            name: case.name.to_owned(),
+            verdict,
+            points: 0,
+            max_points: case.points,
+            output: None,
+            checker_message: None,
+        }
+    }
+}
+
+// errors caused by what the submission did rather than by the sandbox
+fn failed_verdict(err: &SandboxError) -> Option<Verdict> {
+    match err {
+        SandboxError::Timeout { .. } => Some(Verdict::TimeLimitExceeded),
+        SandboxError::InvalidBytesStdOut { .. } => Some(Verdict::WrongAnswer),
+        SandboxError::InvalidBytesStdErr { .. }
+        | SandboxError::WriteToStdin(_)
+        | SandboxError::CloseStdin(_) => Some(Verdict::RuntimeError),
+        _ => None,
+    }
+}
+
+impl JudgeReport {
+    fn compile_failed(cases: &[TestCase], message: String) -> Self {
+        JudgeReport {
+            cases: cases
+                .iter()
+                .map(|case| CaseResult::failed(case, Verdict::CompileError))
+                .collect(),
+            compile_error: Some(message),
+        }
+    }
+
+    pub fn cases(&self) -> impl Iterator<Item = &CaseResult> {
+        self.cases.iter()
+    }
+
+    pub fn compile_error(&self) -> Option<&str> {
+        self.compile_error.as_deref()
+    }
+
+    pub fn score(&self) -> u32 {
+        self.cases().map(|case| case.points).sum()
+    }
+
+    pub fn max_score(&self) -> u32 {
+        self.cases().map(|case| case.max_points).sum()
+    }
+
+    pub fn passed(&self) -> usize {
+        self.cases()
+            .filter(|case| case.verdict == Verdict::Accepted)
+            .count()
+    }
+
+    // the first case that was not accepted decides the overall verdict
+    pub fn verdict(&self) -> Verdict {
+        self.cases()
+            .map(|case| case.verdict)
+            .find(|verdict| *verdict != Verdict::Accepted)
+            .unwrap_or(Verdict::Accepted)
+    }
+}
+
+impl<'a> InteractiveJudge<'a> {
+    pub fn new(sandbox: &'a DockerSandbox, interactor: Program) -> Self {
+        InteractiveJudge {
+            sandbox,
+            interactor,
+            time_limit: Duration::from_secs(2),
+            limits: ResourceLimits {
+                memory_bytes: Some(256 * 1024 * 1024),
+                cpus: Some(1.0),
+            },
+        }
+    }
+
+    pub fn time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = time_limit;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn judge(
+        &self,
+        submission: &Program,
+        test_input: &str,
+    ) -> Result<InteractionResult, SandboxError> {
+        let interactor_options =
+            SessionOptions::default().input_file(INTERACTOR_INPUT_FILE, test_input.as_bytes());
+        let mut interactor = self
+            .sandbox
+            .start_session_with_options(
+                &self.interactor.code_file,
+                self.interactor.lang,
+                &interactor_options,
+            )
+            .await?;
+        let submission_options = SessionOptions::default().limits(self.limits);
+        // both sides hold one run permit between them, a second one could wait forever on the
+        // concurrency limit while the interactor keeps the first
+        let started = self
+            .sandbox
+            .start_session_with_permit(
+                &submission.code_file,
+                submission.lang,
+                &submission_options,
+                Some(interactor.share_permit()),
+            )
+            .await;
+        let mut submission = match started {
+            Ok(session) => session,
+            Err(SandboxError::CompileFailed { stderr, .. }) => {
+                // the interactor may have given up on its own already
+                let _ = interactor.kill().await;
+                let interactor = interactor.wait().await?;
+                return Ok(InteractionResult {
+                    verdict: Verdict::CompileError,
+                    interactor: Some(interactor),
+                    submission: None,
+                    compile_error: Some(stderr),
+                });
+            }
+            Err(err) => return Err(err),
+        };
+
+        let deadline = Instant::now() + self.time_limit;
+        let interaction =
+            time::timeout_at(deadline, cross_connect(&mut interactor, &mut submission)).await;
+        if !matches!(interaction, Ok(Ok(Interaction::Finished(_)))) {
+            // either side may have exited already, which docker reports as a failed kill
+            let _ = submission.kill().await;
+            let _ = interactor.kill().await;
+        }
+        // both sessions are always waited for so that neither container outlives the judging,
+        // a side that keeps running after the other finished only gets the rest of the limit
+        let (interactor, submission) = futures::join!(
+            interactor.wait_until(deadline),
+            submission.wait_until(deadline)
+        );
+        let (interactor, interactor_killed) = interactor?;
+        let first_done = match interaction {
+            Ok(Ok(Interaction::Finished(side))) => Some(side),
+            Ok(Ok(Interaction::InvalidOutput)) => {
+                return Ok(InteractionResult {
+                    verdict: Verdict::WrongAnswer,
+                    interactor: Some(interactor),
+                    submission: submission.ok().map(|(submission, _)| submission),
+                    compile_error: None,
+                })
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(_) => None,
+        };
+        let (submission, submission_killed) = match submission {
+            Ok(submission) => submission,
+            Err(SandboxError::InvalidBytesStdOut { .. }) => {
+                return Ok(InteractionResult {
+                    verdict: Verdict::WrongAnswer,
+                    interactor: Some(interactor),
+                    submission: None,
+                    compile_error: None,
+                })
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(InteractionResult {
+            verdict: verdict(
+                &interactor,
+                &submission,
+                first_done,
+                interactor_killed || submission_killed,
+            ),
+            interactor: Some(interactor),
+            submission: Some(submission),
+            compile_error: None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Interactor,
+    Submission,
+}
+
+enum Interaction {
+    // the side whose output ended first
+    Finished(Side),
+    // the submission wrote something that is not text or a line without end, which no
+    // interactor can accept
+    InvalidOutput,
+}
+
+async fn cross_connect(
+    interactor: &mut Session<'_>,
+    submission: &mut Session<'_>,
+) -> Result<Interaction, SandboxError> {
+    let mut first_done = None;
+    let mut interactor_open = true;
+    let mut submission_open = true;
+    while interactor_open || submission_open {
+        tokio::select! {
+            line = interactor.read_stdout_line(), if interactor_open => match line? {
+                Some(line) => forward(submission, &line).await?,
+                None => {
+                    interactor_open = false;
+                    first_done.get_or_insert(Side::Interactor);
+                    close(submission).await?;
+                }
+            },
+            line = submission.read_stdout_line(), if submission_open => match line {
+                Ok(Some(line)) => forward(interactor, &line).await?,
+                Ok(None) => {
+                    submission_open = false;
+                    first_done.get_or_insert(Side::Submission);
+                    close(interactor).await?;
+                }
+                Err(SandboxError::InvalidBytesStdOut { .. } | SandboxError::LineTooLong { .. }) => {
+                    return Ok(Interaction::InvalidOutput)
+                }
+                Err(err) => return Err(err),
+            },
+        }
+    }
+    Ok(Interaction::Finished(
+        first_done.unwrap_or(Side::Interactor),
+    ))
+}
+
+// a peer that already exited or stopped reading loses the line, the same way it would with a
+// real pipe; its exit code decides the verdict
+async fn forward(session: &mut Session<'_>, line: &str) -> Result<(), SandboxError> {
+    match session.write_stdin(&format!("{line}\n")).await {
+        Err(SandboxError::StdinClosed) => Ok(()),
+        Err(SandboxError::WriteToStdin(err)) if err.kind() == std::io::ErrorKind::BrokenPipe => {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+async fn close(session: &mut Session<'_>) -> Result<(), SandboxError> {
+    match session.close_stdin().await {
+        Err(SandboxError::CloseStdin(err)) if err.kind() == std::io::ErrorKind::BrokenPipe => {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// whichever side finished first decides: a submission that crashes makes the interactor fail on
+// end of input, and one that was rejected may crash or hang waiting for more input; any other
+// side still running at the time limit exceeded it
+fn verdict(
+    interactor: &RunOutput,
+    submission: &RunOutput,
+    first_done: Option<Side>,
+    timed_out: bool,
+) -> Verdict {
+    let rejected = interactor.exit_code == 1;
+    let submission_verdict = if submission.oom_killed {
+        Some(Verdict::MemoryLimitExceeded)
+    } else if submission.exit_code != 0 {
+        Some(Verdict::RuntimeError)
+    } else {
+        None
+    };
+    match first_done {
+        None if rejected => Verdict::WrongAnswer,
+        None => Verdict::TimeLimitExceeded,
+        Some(Side::Interactor) if rejected => Verdict::WrongAnswer,
+        Some(_) if timed_out => Verdict::TimeLimitExceeded,
+        Some(_) => match (submission_verdict, interactor.exit_code) {
+            (Some(verdict), _) => verdict,
+            (None, 0) => Verdict::Accepted,
+            (None, 1) => Verdict::WrongAnswer,
+            (None, _) => Verdict::JudgeError,
+        },
+    }
+}
+
+fn floats_match(output: &str, expected: &str, tolerance: f64) -> bool {
+    let output = output.split_whitespace().collect::<Vec<_>>();
+    let expected = expected.split_whitespace().collect::<Vec<_>>();
+    output.len() == expected.len()
+        && output.iter().zip(&expected).all(|(actual, expected)| {
+            match (actual.parse::<f64>(), expected.parse::<f64>()) {
+                (Ok(actual), Ok(expected)) => {
+                    let diff = (actual - expected).abs();
+                    diff <= tolerance || diff <= tolerance * expected.abs()
+                }
+                _ => actual == expected,
+            }
+        })
+}
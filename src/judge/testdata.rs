@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::TestCase;
+use crate::output::list_files;
+
+pub const METADATA_FILE: &str = "tests.toml";
+pub const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+pub struct TestSuite {
+    cases: Vec<TestCase>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SuiteMetadata {
+    time_limit_ms: Option<u64>,
+    points: Option<u32>,
+    #[serde(default)]
+    tests: HashMap<String, CaseMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaseMetadata {
+    time_limit_ms: Option<u64>,
+    points: Option<u32>,
+}
+
+#[derive(Default)]
+struct CaseFiles {
+    input: Option<String>,
+    output: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum TestDataError {
+    #[error("failed to read test data at {path:?}")]
+    Read {
+        path: PathBuf,
+
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to open test data archive {path:?}")]
+    OpenArchive {
+        path: PathBuf,
+
+        #[source]
+        source: zip::result::ZipError,
+    },
+
+    #[error("test data file {path:?} is not valid utf-8")]
+    InvalidText { path: PathBuf },
+
+    #[error("invalid test metadata in {path:?}")]
+    ParseMetadata {
+        path: PathBuf,
+
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("test {name:?} has an input file but no matching .out file")]
+    MissingOutput { name: String },
+
+    #[error("test {name:?} has an output file but no matching .in file")]
+    MissingInput { name: String },
+
+    #[error("test metadata refers to unknown test {name:?}")]
+    UnknownTest { name: String },
+
+    #[error("no .in/.out test pairs found in {path:?}")]
+    NoTests { path: PathBuf },
+}
+
+impl TestSuite {
+    pub fn load<T>(path: T) -> Result<Self, TestDataError>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Self::from_dir(path)
+        } else {
+            Self::from_zip(path)
+        }
+    }
+
+    pub fn from_dir<T>(dir: T) -> Result<Self, TestDataError>
+    where
+        T: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        let read_err = |path, err| TestDataError::Read { path, source: err };
+        let mut files = Vec::new();
+        for path in list_files(dir, read_err)? {
+            if !is_test_file(&path) {
+                continue;
+            }
+            let contents =
+                fs::read(dir.join(&path)).map_err(|err| read_err(dir.join(&path), err))?;
+            files.push((path, contents));
+        }
+        Self::from_files(dir, files)
+    }
+
+    pub fn from_zip<T>(archive: T) -> Result<Self, TestDataError>
+    where
+        T: AsRef<Path>,
+    {
+        let archive_path = archive.as_ref();
+        let archive_err = |err| TestDataError::OpenArchive {
+            path: archive_path.to_owned(),
+            source: err,
+        };
+        let file = File::open(archive_path).map_err(|err| TestDataError::Read {
+            path: archive_path.to_owned(),
+            source: err,
+        })?;
+        let mut archive = zip::ZipArchive::new(file).map_err(archive_err)?;
+        let mut files = Vec::new();
+        for idx in 0..archive.len() {
+            let mut entry = archive.by_index(idx).map_err(archive_err)?;
+            let Some(path) = entry.enclosed_name() else {
+                continue;
+            };
+            if entry.is_dir() || !is_test_file(&path) {
+                continue;
+            }
+            let mut contents = Vec::new();
+            entry
+                .read_to_end(&mut contents)
+                .map_err(|err| TestDataError::Read {
+                    path: archive_path.join(&path),
+                    source: err,
+                })?;
+            files.push((path, contents));
+        }
+        Self::from_files(archive_path, files)
+    }
+
+    pub fn cases(&self) -> &[TestCase] {
+        &self.cases
+    }
+
+    pub fn max_score(&self) -> u32 {
+        self.cases.iter().map(|case| case.points).sum()
+    }
+
+    fn from_files(root: &Path, files: Vec<(PathBuf, Vec<u8>)>) -> Result<Self, TestDataError> {
+        let mut metadata = SuiteMetadata::default();
+        let mut pairs = BTreeMap::<String, CaseFiles>::new();
+        for (path, contents) in files {
+            let text = String::from_utf8(contents).map_err(|_| TestDataError::InvalidText {
+                path: root.join(&path),
+            })?;
+            if path == Path::new(METADATA_FILE) {
+                metadata = toml::from_str(&text).map_err(|err| TestDataError::ParseMetadata {
+                    path: root.join(&path),
+                    source: err,
+                })?;
+                continue;
+            }
+            let name = path.with_extension("").display().to_string();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("in") => pairs.entry(name).or_default().input = Some(text),
+                Some("out") => pairs.entry(name).or_default().output = Some(text),
+                _ => {}
+            }
+        }
+        if pairs.is_empty() {
+            return Err(TestDataError::NoTests {
+                path: root.to_owned(),
+            });
+        }
+        if let Some(name) = metadata
+            .tests
+            .keys()
+            .find(|name| !pairs.contains_key(*name))
+        {
+            return Err(TestDataError::UnknownTest {
+                name: name.to_owned(),
+            });
+        }
+
+        let mut cases = Vec::new();
+        for (name, files) in pairs {
+            let input = files.input.ok_or_else(|| TestDataError::MissingInput {
+                name: name.to_owned(),
+            })?;
+            let output = files.output.ok_or_else(|| TestDataError::MissingOutput {
+                name: name.to_owned(),
+            })?;
+            let case_metadata = metadata.tests.get(&name);
+            let time_limit_ms = case_metadata
+                .and_then(|case| case.time_limit_ms)
+                .or(metadata.time_limit_ms);
+            let points = case_metadata
+                .and_then(|case| case.points)
+                .or(metadata.points)
+                .unwrap_or(1);
+            let timeout = time_limit_ms.map_or(DEFAULT_TIME_LIMIT, Duration::from_millis);
+            cases.push(TestCase::new(&name, &input, &output, timeout).points(points));
+        }
+        cases.sort_by_cached_key(|case| natural_key(&case.name));
+        Ok(TestSuite { cases })
+    }
+}
+
+// everything else in a test data directory, such as generators or images, is left alone
+fn is_test_file(path: &Path) -> bool {
+    path == Path::new(METADATA_FILE)
+        || matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("in" | "out")
+        )
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum NamePart {
+    // digits without leading zeros compare by length first, which orders them by value
+    Number(usize, String),
+    Text(String),
+}
+
+// numbered tests sort by value so that "2" comes before "10" and "case2" before "case10"; the
+// full name breaks ties between names that only differ in leading zeros
+fn natural_key(name: &str) -> (Vec<NamePart>, String) {
+    let mut parts = Vec::new();
+    let mut rest = name;
+    while let Some(first) = rest.chars().next() {
+        let is_digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        parts.push(if is_digit {
+            let digits = run.trim_start_matches('0');
+            NamePart::Number(digits.len(), digits.to_owned())
+        } else {
+            NamePart::Text(run.to_owned())
+        });
+        rest = tail;
+    }
+    (parts, name.to_owned())
+}
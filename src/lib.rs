@@ -1,2 +1,12 @@
+pub mod backend;
+pub mod cleanup;
+pub mod config;
+pub mod judge;
+pub mod mock;
+pub mod output;
+mod queue;
 pub mod sandbox;
+pub mod session;
+pub mod transcript;
+pub mod wasm;
 pub mod workflow;
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time;
+
+use crate::output::{InputFile, OutputFile};
+use crate::sandbox::{
+    cancelled, resolve_version, Language, PhaseTimings, ResourceUsage, RunOptions, RunOutput,
+    Sandbox, SandboxError,
+};
+use crate::transcript::Transcript;
+
+#[derive(Default)]
+pub struct MockSandbox {
+    by_file: HashMap<PathBuf, MockResponse>,
+    by_call: HashMap<usize, MockResponse>,
+    fallback: Option<MockResponse>,
+    versions: HashMap<Language, Vec<String>>,
+    calls: Mutex<Vec<MockCall>>,
+}
+
+#[derive(Clone, Default)]
+pub struct MockResponse {
+    stdout: String,
+    stderr: String,
+    exit_code: u64,
+    oom_killed: bool,
+    delay: Duration,
+    files: Vec<OutputFile>,
+    usage: ResourceUsage,
+    error: Option<Arc<dyn Fn() -> SandboxError + Send + Sync>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockCall {
+    pub code_file: PathBuf,
+    pub lang: Language,
+    pub version: Option<String>,
+    pub stdin: Option<String>,
+    pub input_files: Vec<InputFile>,
+}
+
+impl MockSandbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_file<T>(mut self, code_file: T, response: MockResponse) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.by_file.insert(code_file.as_ref().to_owned(), response);
+        self
+    }
+
+    // calls are counted in the order the sandbox is asked to run code, which matches the step
+    // index only for workflows that run every step exactly once and in sequence
+    pub fn on_call(mut self, call_idx: usize, response: MockResponse) -> Self {
+        self.by_call.insert(call_idx, response);
+        self
+    }
+
+    pub fn fallback(mut self, response: MockResponse) -> Self {
+        self.fallback = Some(response);
+        self
+    }
+
+    pub fn version(mut self, lang: Language, version: &str) -> Self {
+        self.versions
+            .entry(lang)
+            .or_default()
+            .push(version.to_owned());
+        self
+    }
+
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl MockResponse {
+    pub fn stdout(stdout: &str) -> Self {
+        MockResponse {
+            stdout: stdout.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    pub fn error<F>(error: F) -> Self
+    where
+        F: Fn() -> SandboxError + Send + Sync + 'static,
+    {
+        MockResponse {
+            error: Some(Arc::new(error)),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_stderr(mut self, stderr: &str) -> Self {
+        self.stderr = stderr.to_owned();
+        self
+    }
+
+    pub fn with_exit_code(mut self, exit_code: u64) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    pub fn with_oom_killed(mut self) -> Self {
+        self.oom_killed = true;
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_file<T>(mut self, path: T, contents: &[u8]) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.files.push(OutputFile {
+            path: path.as_ref().to_owned(),
+            contents: contents.to_vec(),
+        });
+        self
+    }
+
+    pub fn with_usage(mut self, usage: ResourceUsage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    async fn respond(&self, options: &RunOptions) -> Result<RunOutput, SandboxError> {
+        let timeout = options.timeout;
+        let delay = async {
+            tokio::select! {
+                () = time::sleep(self.delay) => Ok(()),
+                () = cancelled(options.cancel.as_ref()) => Err(SandboxError::Cancelled),
+            }
+        };
+        time::timeout(timeout, delay)
+            .await
+            .map_err(|err| SandboxError::Timeout {
+                duration: timeout,
+                source: err,
+            })??;
+        if let Some(error) = &self.error {
+            return Err(error());
+        }
+        Ok(RunOutput {
+            stdout: self.stdout.to_owned(),
+            stderr: self.stderr.to_owned(),
+            exit_code: self.exit_code,
+            oom_killed: self.oom_killed,
+            files: self.files.clone(),
+            usage: self.usage,
+            timings: PhaseTimings {
+                run: self.delay,
+                ..PhaseTimings::default()
+            },
+            queue_wait: Duration::ZERO,
+            transcript: Transcript::from_streams(&self.stdout, &self.stderr, self.delay),
+        })
+    }
+}
+
+#[async_trait]
+impl Sandbox for MockSandbox {
+    async fn run_code_with_options(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        let call_idx = {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(MockCall {
+                code_file: code_file.to_owned(),
+                lang,
+                version: options.version.to_owned(),
+                stdin: options.stdin.to_owned(),
+                input_files: options.input_files.to_owned(),
+            });
+            calls.len() - 1
+        };
+        let response = self
+            .by_call
+            .get(&call_idx)
+            .or_else(|| self.by_file.get(code_file))
+            .or(self.fallback.as_ref())
+            .unwrap_or_else(|| {
+                panic!("mock sandbox has no response for call {call_idx} of {code_file:?}")
+            });
+        response.respond(options).await
+    }
+
+    fn resolve_version(&self, lang: Language, requirement: &str) -> Option<String> {
+        let versions = self.versions.get(&lang)?;
+        resolve_version(requirement, versions.iter().map(String::as_str)).map(str::to_owned)
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use glob::Pattern;
+
+use crate::sandbox::SandboxError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    pub contents: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub contents: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OutputLimits {
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        OutputLimits {
+            max_file_bytes: 8 * 1024 * 1024,
+            max_total_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+pub(crate) fn collect_output_files(
+    dir: &Path,
+    globs: &[String],
+    limits: OutputLimits,
+) -> Result<Vec<OutputFile>, SandboxError> {
+    if globs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let patterns = compile_globs(globs)?;
+
+    let mut files = Vec::new();
+    let mut total_bytes = 0;
+    let read_err = |path: PathBuf, err| SandboxError::ReadOutputFile { path, source: err };
+    for path in list_files(dir, read_err)? {
+        if !patterns.iter().any(|pattern| pattern.matches_path(&path)) {
+            continue;
+        }
+        let host_path = dir.join(&path);
+        let Some(file) =
+            open_regular_file(&host_path).map_err(|err| read_err(host_path.to_owned(), err))?
+        else {
+            continue;
+        };
+        let size = file
+            .metadata()
+            .map_err(|err| read_err(host_path.to_owned(), err))?
+            .len();
+        if size > limits.max_file_bytes {
+            return Err(SandboxError::OutputFileTooLarge {
+                path,
+                size,
+                limit: limits.max_file_bytes,
+            });
+        }
+        total_bytes += size;
+        if total_bytes > limits.max_total_bytes {
+            return Err(SandboxError::OutputFilesTooLarge {
+                limit: limits.max_total_bytes,
+            });
+        }
+        let mut contents = Vec::new();
+        file.take(size)
+            .read_to_end(&mut contents)
+            .map_err(|err| read_err(host_path, err))?;
+        files.push(OutputFile { path, contents });
+    }
+    Ok(files)
+}
+
+pub(crate) fn pack_sandbox_dir(dir: &Path, workdir: &Path) -> Result<Vec<u8>, SandboxError> {
+    let pack_err = |path, err| SandboxError::PackSandboxFile { path, source: err };
+    let mut archive = tar::Builder::new(Vec::new());
+    let root = workdir.strip_prefix("/").unwrap_or(workdir);
+    for path in list_files(dir, pack_err)? {
+        archive
+            .append_path_with_name(dir.join(&path), root.join(&path))
+            .map_err(|err| pack_err(path, err))?;
+    }
+    archive
+        .into_inner()
+        .map_err(|err| pack_err(dir.to_owned(), err))
+}
+
+// globs of None unpacks every file, otherwise only the files the caller asked for
+pub(crate) fn unpack_sandbox_archive(
+    archive_path: &Path,
+    dir: &Path,
+    globs: Option<&[String]>,
+) -> Result<(), SandboxError> {
+    let patterns = globs.map(compile_globs).transpose()?;
+    let unpack_err = |err| SandboxError::UnpackArchive {
+        path: archive_path.to_owned(),
+        source: err,
+    };
+    let file = fs::File::open(archive_path).map_err(unpack_err)?;
+    let mut archive = tar::Archive::new(file);
+    for entry in archive.entries().map_err(unpack_err)? {
+        let mut entry = entry.map_err(unpack_err)?;
+        // links are skipped so an archive can't make later writes escape the directory
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let entry_path = entry.path().map_err(unpack_err)?.into_owned();
+        // the archive is rooted at the sandbox directory's own name
+        let path = entry_path.components().skip(1).collect::<PathBuf>();
+        let is_relative = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_relative || path.as_os_str().is_empty() {
+            continue;
+        }
+        if let Some(patterns) = &patterns {
+            if !patterns.iter().any(|pattern| pattern.matches_path(&path)) {
+                continue;
+            }
+        }
+        let host_path = dir.join(&path);
+        if let Some(parent) = host_path.parent() {
+            fs::create_dir_all(parent).map_err(unpack_err)?;
+        }
+        entry.unpack(&host_path).map_err(unpack_err)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_input_files(dir: &Path, files: &[InputFile]) -> Result<(), SandboxError> {
+    for file in files {
+        let is_relative = file
+            .path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_relative || file.path.as_os_str().is_empty() {
+            return Err(SandboxError::InvalidInputFile(file.path.to_owned()));
+        }
+        let host_path = dir.join(&file.path);
+        let write_err = |err| SandboxError::WriteInputFile {
+            path: file.path.to_owned(),
+            source: err,
+        };
+        if let Some(parent) = host_path.parent() {
+            fs::create_dir_all(parent).map_err(write_err)?;
+        }
+        fs::write(&host_path, &file.contents).map_err(write_err)?;
+    }
+    Ok(())
+}
+
+// the sandboxed program may have swapped the file for a link or a fifo since it
+// was listed, so the check is repeated on the opened handle
+fn open_regular_file(path: &Path) -> io::Result<Option<File>> {
+    let file = match fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(err) if err.raw_os_error() == Some(libc::ELOOP) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(file.metadata()?.is_file().then_some(file))
+}
+
+fn compile_globs(globs: &[String]) -> Result<Vec<Pattern>, SandboxError> {
+    globs
+        .iter()
+        .map(|glob| {
+            Pattern::new(glob).map_err(|err| SandboxError::InvalidOutputGlob {
+                pattern: glob.to_owned(),
+                source: err,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn list_files<E, F>(dir: &Path, map_err: F) -> Result<Vec<PathBuf>, E>
+where
+    F: Fn(PathBuf, io::Error) -> E,
+{
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let entries =
+            fs::read_dir(dir.join(&relative)).map_err(|err| map_err(dir.join(&relative), err))?;
+        for entry in entries {
+            let entry = entry.map_err(|err| map_err(dir.join(&relative), err))?;
+            let path = relative.join(entry.file_name());
+            // links are never followed, the sandboxed program can point them anywhere on the host
+            let file_type = entry
+                .file_type()
+                .map_err(|err| map_err(dir.join(&path), err))?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::sandbox::SandboxError;
+
+pub(crate) struct RunQueue {
+    permits: Option<Arc<Semaphore>>,
+    max_queued: Option<usize>,
+    queued: AtomicUsize,
+}
+
+pub(crate) struct RunPermit {
+    pub(crate) wait: Duration,
+    permit: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl RunQueue {
+    pub(crate) fn new(max_concurrent: Option<usize>, max_queued: Option<usize>) -> Self {
+        RunQueue {
+            permits: max_concurrent.map(|max| Arc::new(Semaphore::new(max))),
+            max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) async fn acquire(&self) -> Result<RunPermit, SandboxError> {
+        let Some(permits) = &self.permits else {
+            return Ok(RunPermit {
+                wait: Duration::ZERO,
+                permit: None,
+            });
+        };
+        // tokio's semaphore hands released permits to waiters in fifo order, so
+        // this only succeeds when nobody is queued ahead of us
+        if let Ok(permit) = permits.clone().try_acquire_owned() {
+            return Ok(RunPermit {
+                wait: Duration::ZERO,
+                permit: Some(Arc::new(permit)),
+            });
+        }
+
+        let start = Instant::now();
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+        let _guard = QueuedGuard(&self.queued);
+        if let Some(max_queued) = self.max_queued {
+            if queued >= max_queued {
+                return Err(SandboxError::Overloaded { max_queued });
+            }
+        }
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("run queue semaphore is never closed");
+        Ok(RunPermit {
+            wait: start.elapsed(),
+            permit: Some(Arc::new(permit)),
+        })
+    }
+}
+
+impl RunPermit {
+    // programs that only make sense together, like an interactor and its submission, run under
+    // one permit; the slot is released once the last of them is done
+    pub(crate) fn share(&self) -> RunPermit {
+        RunPermit {
+            wait: Duration::ZERO,
+            permit: self.permit.clone(),
+        }
+    }
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
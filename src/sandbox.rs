@@ -1,32 +1,141 @@
+use async_trait::async_trait;
 use futures::AsyncWriteExt;
-use futures::StreamExt;
 use futures::TryStreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use shiplift::tty::TtyChunk;
 use shiplift::tty::TtyChunk::{StdErr, StdIn, StdOut};
-use shiplift::{BuildOptions, Container, ContainerOptions, Docker, RmContainerOptions};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::future::{self, Future};
 use std::path::{Path, PathBuf};
 use std::str::from_utf8;
+use std::sync::Arc;
 use std::time::Duration;
-use std::{fs, vec};
-use tempdir::TempDir;
 use thiserror::Error;
-use tokio::time;
 use tokio::time::error::Elapsed;
+use tokio::time::{self, Instant};
+pub use tokio_util::sync::CancellationToken;
+
+use crate::backend::DockerBackend;
+use crate::backend::{AttachedContainer, ContainerBackend, ContainerExit, ContainerSpec};
+pub use crate::backend::{ContainerSummary, NetworkPolicy, TransferMode};
+pub use crate::backend::{DaemonVersion, DockerEndpoint, ResourceLimits, ResourceUsage, TlsConfig};
+use crate::backend::{Isolation, LocalBackend, LocalLimits};
+use crate::backend::{CONTEXT_HASH_LABEL, SANDBOX_ID_LABEL};
+pub use crate::cleanup::CleanupReport;
+use crate::cleanup::{cleanup_stale, new_sandbox_id, WorkDir, DEFAULT_STALE_AGE};
+use crate::config::{ConfigError, SandboxConfig};
+use crate::output::{collect_output_files, list_files, write_input_files};
+use crate::output::{pack_sandbox_dir, unpack_sandbox_archive};
+pub use crate::output::{InputFile, OutputFile, OutputLimits};
+use crate::queue::{RunPermit, RunQueue};
+use crate::session::Session;
+use crate::transcript::{StreamKind, Transcript};
+
+pub(crate) const SANDBOX_DIR: &str = "/home/sandbox";
 
 pub struct DockerSandbox {
-    docker: Docker,
+    backend: Arc<dyn ContainerBackend>,
     image_tag: String,
+    language_images: HashMap<Language, String>,
+    versions: HashMap<Language, HashMap<String, LanguageVersion>>,
+    sandbox_id: String,
+    sandbox_dir: PathBuf,
+    languages: HashMap<Language, LanguageSpec>,
+    limits: ResourceLimits,
+    network: NetworkPolicy,
+    transfer: TransferMode,
+    queue: RunQueue,
+    stale_sweep: Option<Result<CleanupReport, SandboxError>>,
 }
 
-#[derive(Clone, Copy)]
+pub type BuildProgress = Box<dyn Fn(&str) + Send + Sync>;
+
+// without a directory the image is expected to exist already
+struct LanguageImage {
+    directory: Option<PathBuf>,
+    image_tag: String,
+}
+
+pub struct DockerSandboxBuilder {
+    directory: Option<PathBuf>,
+    image_tag: String,
+    image_archives: Vec<PathBuf>,
+    language_images: HashMap<Language, LanguageImage>,
+    versions: HashMap<Language, HashMap<String, LanguageVersion>>,
+    backend: Option<Box<dyn ContainerBackend>>,
+    endpoint: Option<DockerEndpoint>,
+    build_progress: Option<BuildProgress>,
+    max_concurrent: Option<usize>,
+    max_queued: Option<usize>,
+    stale_age: Option<Duration>,
+    sandbox_dir: PathBuf,
+    languages: HashMap<Language, LanguageSpec>,
+    limits: ResourceLimits,
+    network: NetworkPolicy,
+    transfer: TransferMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Docker,
+    Podman,
+    Local,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Language {
     Python,
     JavaScript,
     Java,
 }
 
+// commands may refer to the code file as {source} and to its name without extension as {stem}
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageSpec {
+    pub extension: String,
+    #[serde(default)]
+    pub compile: Vec<String>,
+    pub run: Vec<String>,
+}
+
+// a version swaps the commands, the image or both, and falls back to the
+// language defaults for whatever it leaves out
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LanguageVersion {
+    pub spec: Option<LanguageSpec>,
+    pub image_tag: Option<String>,
+}
+
+// code that is compiled once and then run many times, such as a judged submission
+pub struct CompiledCode {
+    code_file: PathBuf,
+    lang: Language,
+    build: Option<CompiledBuild>,
+}
+
+struct CompiledBuild {
+    dir: WorkDir,
+    image_tag: String,
+    run_cmd: Vec<String>,
+}
+
+struct Runtime<'a> {
+    spec: &'a LanguageSpec,
+    image_tag: &'a str,
+}
+
 #[derive(Error, Debug)]
 pub enum SandboxError {
+    #[error("invalid sandbox config")]
+    InvalidConfig(#[source] Box<ConfigError>),
+
     #[error("directory {0:?} does not contain a dockerfile named 'Dockerfile'")]
     MissingDockerfile(PathBuf),
 
@@ -38,6 +147,66 @@ pub enum SandboxError {
         source: std::io::Error,
     },
 
+    #[error("invalid docker endpoint {url:?}")]
+    InvalidEndpoint { url: String },
+
+    #[error("tls certificate directory {cert_dir:?} does not contain {file:?}")]
+    MissingTlsFile { cert_dir: PathBuf, file: String },
+
+    #[error("tls certificates in {cert_dir:?} could not be loaded")]
+    InvalidTlsFiles {
+        cert_dir: PathBuf,
+
+        #[source]
+        source: openssl::error::ErrorStack,
+    },
+
+    #[error("failed to open a tunnel to the daemon")]
+    OpenTunnel(#[source] std::io::Error),
+
+    #[error("{daemon} is unreachable")]
+    DaemonUnreachable {
+        daemon: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to read the docker build context at {path:?}")]
+    ReadBuildContext {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("docker image with tag {image_tag:?} does not exist")]
+    MissingImage {
+        image_tag: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to read docker image archive {path:?}")]
+    ReadImageArchive {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to load docker image archive {path:?}")]
+    LoadImage {
+        path: PathBuf,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to load docker image archive {path:?}: {message}")]
+    LoadImageFailed { path: PathBuf, message: String },
+
     #[error("failed to create temp directory")]
     CreateTempDirectory(#[source] std::io::Error),
 
@@ -53,8 +222,15 @@ pub enum SandboxError {
         source: std::io::Error,
     },
 
-    #[error("failed to build docker image")]
-    BuildImage(#[source] shiplift::Error),
+    #[error("failed to build docker image at step {step:?}: {message}")]
+    BuildImage {
+        step: Option<String>,
+        message: String,
+        log_tail: Vec<String>,
+    },
+
+    #[error("failed to read the docker image build stream")]
+    BuildImageStream(#[source] shiplift::Error),
 
     #[error("failed to create docker container from image with tag {image_tag:?}")]
     CreateContainer {
@@ -80,9 +256,65 @@ pub enum SandboxError {
         source: shiplift::Error,
     },
 
-    #[error("failed to execute {cmd:?} inside docker container")]
-    Execute {
-        cmd: String,
+    #[error("failed to wait for docker container with id {container_id:?}")]
+    WaitContainer {
+        container_id: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to read the output of docker container with id {container_id:?}")]
+    ReadOutput {
+        container_id: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to copy files into docker container with id {container_id:?}")]
+    CopyToContainer {
+        container_id: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to copy files out of docker container with id {container_id:?}")]
+    CopyFromContainer {
+        container_id: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to pack sandbox file {path:?}")]
+    PackSandboxFile {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to write archive {path:?}")]
+    WriteArchive {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to unpack archive {path:?}")]
+    UnpackArchive {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to kill docker container with id {container_id:?}")]
+    KillContainer {
+        container_id: String,
 
         #[source]
         source: shiplift::Error,
@@ -96,12 +328,158 @@ pub enum SandboxError {
         source: shiplift::Error,
     },
 
+    #[error("failed to list docker containers")]
+    ListContainers(#[source] shiplift::Error),
+
+    #[error("failed to list docker images")]
+    ListImages(#[source] shiplift::Error),
+
+    #[error("failed to remove docker image with id {image_id:?}")]
+    RemoveImage {
+        image_id: String,
+
+        #[source]
+        source: shiplift::Error,
+    },
+
+    #[error("failed to read temp directory {path:?}")]
+    ReadTempDirectory {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to set up the wasm engine")]
+    WasmEngine(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("failed to load wasm module at {path:?}")]
+    LoadWasmModule {
+        path: PathBuf,
+
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("wasm module trapped")]
+    WasmTrap(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("wasm module ran out of fuel")]
+    FuelExhausted,
+
+    #[error("input file path {0:?} must be relative to the sandbox directory")]
+    InvalidInputFile(PathBuf),
+
+    #[error("failed to write input file {path:?}")]
+    WriteInputFile {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("invalid output glob {pattern:?}")]
+    InvalidOutputGlob {
+        pattern: String,
+
+        #[source]
+        source: glob::PatternError,
+    },
+
+    #[error("failed to read output file {path:?}")]
+    ReadOutputFile {
+        path: PathBuf,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("output file {path:?} has {size} bytes, exceeding the limit of {limit} bytes")]
+    OutputFileTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+
+    #[error("output files exceed the total limit of {limit} bytes")]
+    OutputFilesTooLarge { limit: u64 },
+
+    #[error("native step failed")]
+    NativeStep(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("no {lang:?} runtime satisfies version {version:?}")]
+    UnsupportedVersion { lang: Language, version: String },
+
+    #[error("at least one run must be allowed at a time")]
+    NoConcurrentRuns,
+
+    #[error("unknown sandbox backend {0:?}, expected one of docker, podman or local")]
+    UnknownBackend(String),
+
+    #[error("bwrap is not installed; set RUSTYCHAINS_ISOLATION=unshare to run without filesystem isolation")]
+    MissingBubblewrap,
+
+    #[error("unknown local isolation {0:?}, expected bwrap or unshare")]
+    UnknownIsolation(String),
+
+    #[error("failed to spawn {cmd:?}")]
+    SpawnProcess {
+        cmd: String,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("no local process with id {0:?}")]
+    UnknownProcess(String),
+
+    #[error("failed to attach to local process with id {container_id:?}")]
+    AttachToProcess {
+        container_id: String,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to wait for local process with id {container_id:?}")]
+    WaitProcess {
+        container_id: String,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to kill local process with id {container_id:?}")]
+    KillProcess {
+        container_id: String,
+
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to read the output of local process with id {container_id:?}")]
+    ReadProcessOutput {
+        container_id: String,
+
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("failed to write to stdin of container")]
     WriteToStdin(#[source] std::io::Error),
 
     #[error("failed to close the stdin of container")]
     CloseStdin(#[source] std::io::Error),
 
+    #[error("stdin of the session was already closed")]
+    StdinClosed,
+
+    #[error("session wrote a stdout line longer than {limit} bytes")]
+    LineTooLong { limit: usize },
+
+    #[error("compilation failed with exit code {exit_code}")]
+    CompileFailed { exit_code: u64, stderr: String },
+
     #[error("docker container outputted non utf-8 bytes to stdout")]
     InvalidBytesStdOut {
         #[source]
@@ -114,6 +492,12 @@ pub enum SandboxError {
         source: std::str::Utf8Error,
     },
 
+    #[error("code run was cancelled")]
+    Cancelled,
+
+    #[error("sandbox is overloaded, {max_queued} runs are already waiting")]
+    Overloaded { max_queued: usize },
+
     #[error("code run did not complete in {duration:?}")]
     Timeout {
         duration: Duration,
@@ -126,6 +510,117 @@ pub enum SandboxError {
 pub struct RunOutput {
     pub stdout: String,
     pub stderr: String,
+    pub exit_code: u64,
+    pub oom_killed: bool,
+    pub files: Vec<OutputFile>,
+    pub usage: ResourceUsage,
+    pub timings: PhaseTimings,
+    pub queue_wait: Duration,
+    pub transcript: Transcript,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    pub prepare: Duration,
+    pub create: Duration,
+    pub compile: Duration,
+    pub run: Duration,
+    pub collect: Duration,
+    pub cleanup: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    pub timeout: Duration,
+    pub stdin: Option<String>,
+    pub input_files: Vec<InputFile>,
+    pub output_globs: Vec<String>,
+    pub output_limits: OutputLimits,
+    pub limits: ResourceLimits,
+    pub cancel: Option<CancellationToken>,
+    pub version: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SessionOptions {
+    pub input_files: Vec<InputFile>,
+    pub limits: ResourceLimits,
+}
+
+#[async_trait]
+pub trait Sandbox: Send + Sync {
+    async fn run_code_with_options(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError>;
+
+    // sandboxes that keep no build output around compile again on every run
+    async fn compile(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        _timeout: Duration,
+    ) -> Result<CompiledCode, SandboxError> {
+        Ok(CompiledCode {
+            code_file: code_file.to_owned(),
+            lang,
+            build: None,
+        })
+    }
+
+    async fn run_compiled(
+        &self,
+        compiled: &CompiledCode,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        self.run_code_with_options(&compiled.code_file, compiled.lang, options)
+            .await
+    }
+
+    // sandboxes without version selection only offer the default runtime
+    fn resolve_version(&self, _lang: Language, _requirement: &str) -> Option<String> {
+        None
+    }
+}
+
+// lets a workflow or judge share a sandbox with whoever still wants to inspect it, e.g. the
+// calls recorded by a mock
+#[async_trait]
+impl<T> Sandbox for Arc<T>
+where
+    T: Sandbox + ?Sized,
+{
+    async fn run_code_with_options(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        T::run_code_with_options(self, code_file, lang, options).await
+    }
+
+    async fn compile(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        timeout: Duration,
+    ) -> Result<CompiledCode, SandboxError> {
+        T::compile(self, code_file, lang, timeout).await
+    }
+
+    async fn run_compiled(
+        &self,
+        compiled: &CompiledCode,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        T::run_compiled(self, compiled, options).await
+    }
+
+    fn resolve_version(&self, lang: Language, requirement: &str) -> Option<String> {
+        T::resolve_version(self, lang, requirement)
+    }
 }
 
 impl DockerSandbox {
@@ -133,13 +628,109 @@ impl DockerSandbox {
     where
         T: AsRef<Path>,
     {
-        let absolute_path = validate_directory(directory.as_ref())?;
-        let docker = Docker::new();
-        build_image(&docker, &absolute_path, image_tag).await?;
-        Ok(DockerSandbox {
-            docker,
-            image_tag: image_tag.to_owned(),
-        })
+        Self::builder(directory, image_tag).build().await
+    }
+
+    pub fn builder<T>(directory: T, image_tag: &str) -> DockerSandboxBuilder
+    where
+        T: AsRef<Path>,
+    {
+        DockerSandboxBuilder::new(Some(directory.as_ref().to_owned()), image_tag)
+    }
+
+    pub async fn from_existing_image(image_tag: &str) -> Result<Self, SandboxError> {
+        Self::existing_image_builder(image_tag).build().await
+    }
+
+    pub fn existing_image_builder(image_tag: &str) -> DockerSandboxBuilder {
+        DockerSandboxBuilder::new(None, image_tag)
+    }
+
+    pub async fn from_image_archive<T>(archive: T, image_tag: &str) -> Result<Self, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        Self::image_archive_builder(archive, image_tag)
+            .build()
+            .await
+    }
+
+    pub fn image_archive_builder<T>(archive: T, image_tag: &str) -> DockerSandboxBuilder
+    where
+        T: AsRef<Path>,
+    {
+        DockerSandboxBuilder::new(None, image_tag).load_image_archive(archive)
+    }
+
+    pub async fn from_config(config: &SandboxConfig) -> Result<Self, SandboxError> {
+        config
+            .validate()
+            .map_err(|err| SandboxError::InvalidConfig(Box::new(err)))?;
+        let endpoint = config
+            .daemon
+            .docker_endpoint()
+            .map_err(|err| SandboxError::InvalidConfig(Box::new(err)))?;
+        let mut builder = match (&config.image.directory, &config.image.archive) {
+            (Some(directory), _) => Self::builder(directory, &config.image.tag),
+            (None, Some(archive)) => Self::image_archive_builder(archive, &config.image.tag),
+            (None, None) => Self::existing_image_builder(&config.image.tag),
+        }
+        .sandbox_dir(&config.sandbox_dir)
+        .limits(config.limits)
+        .network(config.network)
+        .transfer_mode(config.transfer)
+        .cleanup_stale_on_build(config.cleanup.on_build.then(|| config.cleanup.max_age()));
+        for (lang, spec) in &config.languages {
+            builder = builder.language(*lang, spec.to_owned());
+        }
+        for (lang, versions) in &config.versions {
+            for (version, runtime) in versions {
+                let runtime = LanguageVersion {
+                    spec: runtime.spec.to_owned(),
+                    image_tag: runtime.tag.to_owned(),
+                };
+                builder = builder.language_version(*lang, version, runtime);
+            }
+        }
+        for (lang, image) in &config.image.languages {
+            builder = match &image.directory {
+                Some(directory) => builder.language_dockerfile(*lang, directory, &image.tag),
+                None => builder.language_image(*lang, &image.tag),
+            };
+        }
+        builder.backend = Some(match config.daemon.backend {
+            Some(kind) => connect_backend(kind, endpoint)?,
+            None => default_backend(endpoint)?,
+        });
+        builder.build().await
+    }
+
+    pub async fn ping(&self) -> Result<(), SandboxError> {
+        self.backend.ping().await
+    }
+
+    pub async fn version(&self) -> Result<DaemonVersion, SandboxError> {
+        self.backend.version().await
+    }
+
+    pub fn sandbox_id(&self) -> &str {
+        &self.sandbox_id
+    }
+
+    pub async fn containers(&self) -> Result<Vec<ContainerSummary>, SandboxError> {
+        let mut containers = self.backend.list_containers().await?;
+        containers
+            .retain(|container| container.labels.get(SANDBOX_ID_LABEL) == Some(&self.sandbox_id));
+        Ok(containers)
+    }
+
+    pub async fn cleanup_stale(&self, max_age: Duration) -> Result<CleanupReport, SandboxError> {
+        cleanup_stale(self.backend.as_ref(), &self.sandbox_id, max_age).await
+    }
+
+    // the outcome of the sweep run while building, unless it was turned off
+    pub fn stale_sweep(&self) -> Option<Result<&CleanupReport, &SandboxError>> {
+        self.stale_sweep.as_ref().map(Result::as_ref)
     }
 
     pub async fn run_code<T>(
@@ -152,37 +743,737 @@ impl DockerSandbox {
     where
         T: AsRef<Path>,
     {
-        let temp_dir = TempDir::new("").map_err(SandboxError::CreateTempDirectory)?;
-        let sandbox_files = get_sandbox_files(code_file.as_ref(), lang, temp_dir.as_ref())?;
-        let commands = get_commands(&sandbox_files, lang);
-        copy_code_file(code_file.as_ref(), &sandbox_files.host_src)?;
-        let exec = async {
-            if !&commands.build_cmd.is_empty() {
-                exec_container(
-                    &self.docker,
+        let options = RunOptions::new(timeout).stdin(stdin);
+        self.run_code_with_options(code_file, lang, &options).await
+    }
+
+    pub async fn start_session<T>(
+        &self,
+        code_file: T,
+        lang: Language,
+    ) -> Result<Session<'_>, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        self.start_session_with_options(code_file, lang, &SessionOptions::default())
+            .await
+    }
+
+    pub async fn start_session_with_options<T>(
+        &self,
+        code_file: T,
+        lang: Language,
+        options: &SessionOptions,
+    ) -> Result<Session<'_>, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        self.start_session_with_permit(code_file.as_ref(), lang, options, None)
+            .await
+    }
+
+    pub(crate) async fn start_session_with_permit(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        options: &SessionOptions,
+        permit: Option<RunPermit>,
+    ) -> Result<Session<'_>, SandboxError> {
+        let runtime = self.runtime(lang, None)?;
+        let permit = match permit {
+            Some(permit) => permit,
+            None => self.queue.acquire().await?,
+        };
+        let mut timings = PhaseTimings::default();
+        let prepare_start = Instant::now();
+        let temp_dir = WorkDir::new()?;
+        let sandbox_files = get_sandbox_files(code_file, runtime.spec, temp_dir.as_ref())?;
+        let commands = get_commands(&sandbox_files, runtime.spec);
+        write_input_files(temp_dir.as_ref(), &options.input_files)?;
+        copy_code_file(code_file, &sandbox_files.host_src)?;
+        timings.prepare = prepare_start.elapsed();
+
+        if !commands.build_cmd.is_empty() {
+            let build_output = self
+                .compile_in(
+                    runtime.image_tag,
                     temp_dir.as_ref(),
-                    &self.image_tag,
                     &commands.build_cmd,
                     None,
+                    None,
                 )
                 .await?;
+            timings.compile = build_output.timings.total();
+        }
+
+        let create_start = Instant::now();
+        let spec = self.container_spec(
+            runtime.image_tag,
+            temp_dir.as_ref(),
+            &commands.run_cmd,
+            options.limits,
+        );
+        let container_id = self.backend.create_container(&spec).await?;
+        let started = async {
+            copy_in(self.backend.as_ref(), &spec, &container_id).await?;
+            let attached = self.backend.attach_container(&container_id).await?;
+            timings.create = create_start.elapsed();
+            self.backend.start_container(&container_id).await?;
+            Ok(attached)
+        };
+        let attached = match started.await {
+            Ok(attached) => attached,
+            Err(err) => {
+                // the container may already run when starting failed half way
+                let _ = self.backend.kill_container(&container_id).await;
+                let _ = self.backend.remove_container(&container_id).await;
+                return Err(err);
+            }
+        };
+        Ok(Session::new(
+            &self.backend,
+            container_id,
+            attached.output,
+            attached.stdin,
+            timings,
+            permit,
+            temp_dir,
+        ))
+    }
+
+    pub async fn run_code_with_options<T>(
+        &self,
+        code_file: T,
+        lang: Language,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        let runtime = self.runtime(lang, options.version.as_deref())?;
+        let cancel = options.cancel.as_ref();
+        let permit = tokio::select! {
+            permit = self.queue.acquire() => permit?,
+            () = cancelled(cancel) => return Err(SandboxError::Cancelled),
+        };
+        let prepare_start = Instant::now();
+        let temp_dir = WorkDir::new()?;
+        let sandbox_files = get_sandbox_files(code_file.as_ref(), runtime.spec, temp_dir.as_ref())?;
+        let commands = get_commands(&sandbox_files, runtime.spec);
+        write_input_files(temp_dir.as_ref(), &options.input_files)?;
+        copy_code_file(code_file.as_ref(), &sandbox_files.host_src)?;
+        let prepare = prepare_start.elapsed();
+        // the deadline reaches into each container so an expired run is killed and removed
+        // rather than dropped mid-flight
+        let deadline = Some(Deadline::At {
+            at: Instant::now() + options.timeout,
+            timeout: options.timeout,
+        });
+        let exec = async {
+            let mut usage = ResourceUsage::default();
+            let mut compile = Duration::ZERO;
+            if !&commands.build_cmd.is_empty() {
+                let build_output = self
+                    .compile_in(
+                        runtime.image_tag,
+                        temp_dir.as_ref(),
+                        &commands.build_cmd,
+                        cancel,
+                        deadline,
+                    )
+                    .await?;
+                usage = build_output.usage;
+                compile = build_output.timings.total();
             }
-            exec_container(
-                &self.docker,
+            let spec = self.container_spec(
+                runtime.image_tag,
                 temp_dir.as_ref(),
-                &self.image_tag,
                 &commands.run_cmd,
-                stdin,
+                options.limits,
+            );
+            let mut output = exec_container(
+                self.backend.as_ref(),
+                &spec,
+                options.stdin.as_deref(),
+                Some(&options.output_globs),
+                cancel,
+                deadline,
             )
-            .await
+            .await?;
+            usage.accumulate(&output.usage);
+            output.usage = usage;
+            output.timings.compile = compile;
+            Ok(output)
         };
 
-        time::timeout(timeout, exec)
-            .await
-            .map_err(|err| SandboxError::Timeout {
-                duration: timeout,
-                source: err,
-            })?
+        let mut output = exec.await?;
+        output.timings.prepare = prepare;
+        output.queue_wait = permit.wait;
+        finish_run(output, temp_dir, options)
+    }
+
+    pub async fn compile<T>(
+        &self,
+        code_file: T,
+        lang: Language,
+        timeout: Duration,
+    ) -> Result<CompiledCode, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        let code_file = code_file.as_ref();
+        let runtime = self.runtime(lang, None)?;
+        let _permit = self.queue.acquire().await?;
+        let dir = WorkDir::new()?;
+        let sandbox_files = get_sandbox_files(code_file, runtime.spec, dir.as_ref())?;
+        let commands = get_commands(&sandbox_files, runtime.spec);
+        copy_code_file(code_file, &sandbox_files.host_src)?;
+        if !commands.build_cmd.is_empty() {
+            let deadline = Some(Deadline::At {
+                at: Instant::now() + timeout,
+                timeout,
+            });
+            self.compile_in(
+                runtime.image_tag,
+                dir.as_ref(),
+                &commands.build_cmd,
+                None,
+                deadline,
+            )
+            .await?;
+        }
+        Ok(CompiledCode {
+            code_file: code_file.to_owned(),
+            lang,
+            build: Some(CompiledBuild {
+                dir,
+                image_tag: runtime.image_tag.to_owned(),
+                run_cmd: commands.run_cmd,
+            }),
+        })
+    }
+
+    // the timeout only covers the time the program runs, compiling and starting the container
+    // are not held against it
+    pub async fn run_compiled(
+        &self,
+        compiled: &CompiledCode,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        let Some(build) = &compiled.build else {
+            return self
+                .run_code_with_options(&compiled.code_file, compiled.lang, options)
+                .await;
+        };
+        let cancel = options.cancel.as_ref();
+        let permit = tokio::select! {
+            permit = self.queue.acquire() => permit?,
+            () = cancelled(cancel) => return Err(SandboxError::Cancelled),
+        };
+        let prepare_start = Instant::now();
+        let temp_dir = WorkDir::new()?;
+        copy_dir(build.dir.as_ref(), temp_dir.as_ref())?;
+        write_input_files(temp_dir.as_ref(), &options.input_files)?;
+        let prepare = prepare_start.elapsed();
+
+        let spec = self.container_spec(
+            &build.image_tag,
+            temp_dir.as_ref(),
+            &build.run_cmd,
+            options.limits,
+        );
+        let mut output = exec_container(
+            self.backend.as_ref(),
+            &spec,
+            options.stdin.as_deref(),
+            Some(&options.output_globs),
+            cancel,
+            Some(Deadline::Run(options.timeout)),
+        )
+        .await?;
+        output.timings.prepare = prepare;
+        output.queue_wait = permit.wait;
+        finish_run(output, temp_dir, options)
+    }
+
+    async fn compile_in(
+        &self,
+        image_tag: &str,
+        dir: &Path,
+        build_cmd: &[String],
+        cancel: Option<&CancellationToken>,
+        deadline: Option<Deadline>,
+    ) -> Result<RunOutput, SandboxError> {
+        let spec = self.container_spec(image_tag, dir, build_cmd, ResourceLimits::default());
+        let build_output =
+            exec_container(self.backend.as_ref(), &spec, None, None, cancel, deadline).await?;
+        if build_output.exit_code != 0 {
+            return Err(SandboxError::CompileFailed {
+                exit_code: build_output.exit_code,
+                stderr: build_output.stderr,
+            });
+        }
+        Ok(build_output)
+    }
+}
+
+fn finish_run(
+    mut output: RunOutput,
+    temp_dir: WorkDir,
+    options: &RunOptions,
+) -> Result<RunOutput, SandboxError> {
+    let collect_start = Instant::now();
+    output.files = collect_output_files(
+        temp_dir.as_ref(),
+        &options.output_globs,
+        options.output_limits,
+    )?;
+    output.timings.collect += collect_start.elapsed();
+
+    let cleanup_start = Instant::now();
+    drop(temp_dir);
+    output.timings.cleanup += cleanup_start.elapsed();
+    Ok(output)
+}
+
+impl DockerSandbox {
+    pub fn image_for(&self, lang: Language) -> &str {
+        self.language_images
+            .get(&lang)
+            .map_or(&self.image_tag, String::as_str)
+    }
+
+    pub fn resolve_version(&self, lang: Language, requirement: &str) -> Option<String> {
+        let versions = self.versions.get(&lang)?;
+        resolve_version(requirement, versions.keys().map(String::as_str)).map(str::to_owned)
+    }
+
+    fn runtime(
+        &self,
+        lang: Language,
+        requirement: Option<&str>,
+    ) -> Result<Runtime<'_>, SandboxError> {
+        let default = Runtime {
+            spec: &self.languages[&lang],
+            image_tag: self.image_for(lang),
+        };
+        let Some(requirement) = requirement else {
+            return Ok(default);
+        };
+        let version = self
+            .versions
+            .get(&lang)
+            .and_then(|versions| {
+                let resolved = resolve_version(requirement, versions.keys().map(String::as_str))?;
+                versions.get(resolved)
+            })
+            .ok_or_else(|| SandboxError::UnsupportedVersion {
+                lang,
+                version: requirement.to_owned(),
+            })?;
+        Ok(Runtime {
+            spec: version.spec.as_ref().unwrap_or(default.spec),
+            image_tag: version.image_tag.as_deref().unwrap_or(default.image_tag),
+        })
+    }
+
+    fn container_spec<'a>(
+        &'a self,
+        image_tag: &'a str,
+        host_dir: &'a Path,
+        cmd: &'a [String],
+        limits: ResourceLimits,
+    ) -> ContainerSpec<'a> {
+        ContainerSpec {
+            image_tag,
+            host_dir,
+            workdir: &self.sandbox_dir,
+            cmd,
+            limits: limits.or(self.limits),
+            network: self.network,
+            sandbox_id: &self.sandbox_id,
+            transfer: self.transfer,
+        }
+    }
+}
+
+#[async_trait]
+impl Sandbox for DockerSandbox {
+    async fn run_code_with_options(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        DockerSandbox::run_code_with_options(self, code_file, lang, options).await
+    }
+
+    async fn compile(
+        &self,
+        code_file: &Path,
+        lang: Language,
+        timeout: Duration,
+    ) -> Result<CompiledCode, SandboxError> {
+        DockerSandbox::compile(self, code_file, lang, timeout).await
+    }
+
+    async fn run_compiled(
+        &self,
+        compiled: &CompiledCode,
+        options: &RunOptions,
+    ) -> Result<RunOutput, SandboxError> {
+        DockerSandbox::run_compiled(self, compiled, options).await
+    }
+
+    fn resolve_version(&self, lang: Language, requirement: &str) -> Option<String> {
+        DockerSandbox::resolve_version(self, lang, requirement)
+    }
+}
+
+impl PhaseTimings {
+    pub fn total(&self) -> Duration {
+        self.prepare + self.create + self.compile + self.run + self.collect + self.cleanup
+    }
+}
+
+impl RunOptions {
+    pub fn new(timeout: Duration) -> Self {
+        RunOptions {
+            timeout,
+            stdin: None,
+            input_files: Vec::new(),
+            output_globs: Vec::new(),
+            output_limits: OutputLimits::default(),
+            limits: ResourceLimits::default(),
+            cancel: None,
+            version: None,
+        }
+    }
+
+    pub fn stdin(mut self, stdin: Option<&str>) -> Self {
+        self.stdin = stdin.map(str::to_owned);
+        self
+    }
+
+    pub fn input_file<T>(mut self, path: T, contents: &[u8]) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.input_files.push(InputFile {
+            path: path.as_ref().to_owned(),
+            contents: contents.to_vec(),
+        });
+        self
+    }
+
+    pub fn output_glob(mut self, glob: &str) -> Self {
+        self.output_globs.push(glob.to_owned());
+        self
+    }
+
+    pub fn output_limits(mut self, limits: OutputLimits) -> Self {
+        self.output_limits = limits;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_owned());
+        self
+    }
+}
+
+impl SessionOptions {
+    pub fn input_file<T>(mut self, path: T, contents: &[u8]) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.input_files.push(InputFile {
+            path: path.as_ref().to_owned(),
+            contents: contents.to_vec(),
+        });
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+impl DockerSandboxBuilder {
+    fn new(directory: Option<PathBuf>, image_tag: &str) -> Self {
+        DockerSandboxBuilder {
+            directory,
+            image_tag: image_tag.to_owned(),
+            image_archives: Vec::new(),
+            language_images: HashMap::new(),
+            versions: HashMap::new(),
+            backend: None,
+            endpoint: None,
+            build_progress: None,
+            max_concurrent: None,
+            max_queued: None,
+            stale_age: Some(DEFAULT_STALE_AGE),
+            sandbox_dir: PathBuf::from(SANDBOX_DIR),
+            languages: HashMap::new(),
+            limits: ResourceLimits::default(),
+            network: NetworkPolicy::default(),
+            transfer: TransferMode::default(),
+        }
+    }
+
+    pub fn load_image_archive<T>(mut self, archive: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.image_archives.push(archive.as_ref().to_owned());
+        self
+    }
+
+    pub fn language_image(mut self, lang: Language, image_tag: &str) -> Self {
+        let image = LanguageImage {
+            directory: None,
+            image_tag: image_tag.to_owned(),
+        };
+        self.language_images.insert(lang, image);
+        self
+    }
+
+    pub fn language_dockerfile<T>(mut self, lang: Language, directory: T, image_tag: &str) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        let image = LanguageImage {
+            directory: Some(directory.as_ref().to_owned()),
+            image_tag: image_tag.to_owned(),
+        };
+        self.language_images.insert(lang, image);
+        self
+    }
+
+    pub fn language_version(
+        mut self,
+        lang: Language,
+        version: &str,
+        runtime: LanguageVersion,
+    ) -> Self {
+        self.versions
+            .entry(lang)
+            .or_default()
+            .insert(version.to_owned(), runtime);
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: DockerEndpoint) -> Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    pub fn backend<B>(mut self, backend: B) -> Self
+    where
+        B: ContainerBackend + 'static,
+    {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    pub fn on_build_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.build_progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn max_concurrent_runs(mut self, max: usize) -> Self {
+        self.max_concurrent = Some(max);
+        self
+    }
+
+    pub fn max_queued_runs(mut self, max: usize) -> Self {
+        self.max_queued = Some(max);
+        self
+    }
+
+    pub fn sandbox_dir<T>(mut self, dir: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.sandbox_dir = dir.as_ref().to_owned();
+        self
+    }
+
+    pub fn language(mut self, lang: Language, spec: LanguageSpec) -> Self {
+        self.languages.insert(lang, spec);
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn network(mut self, policy: NetworkPolicy) -> Self {
+        self.network = policy;
+        self
+    }
+
+    pub fn transfer_mode(mut self, mode: TransferMode) -> Self {
+        self.transfer = mode;
+        self
+    }
+
+    pub fn cleanup_stale_on_build(mut self, max_age: Option<Duration>) -> Self {
+        self.stale_age = max_age;
+        self
+    }
+
+    pub async fn build(self) -> Result<DockerSandbox, SandboxError> {
+        // a semaphore without permits would leave every run waiting forever
+        if self.max_concurrent == Some(0) {
+            return Err(SandboxError::NoConcurrentRuns);
+        }
+        let backend = match self.backend {
+            Some(backend) => backend,
+            None => default_backend(self.endpoint)?,
+        };
+        backend.ping().await?;
+        // archives go first so the main and language images may come from them
+        for archive in &self.image_archives {
+            backend.load_image(archive).await?;
+        }
+        let progress = self.build_progress.as_ref();
+        // the shared image is only needed for languages without an image of their own
+        let needs_default = LANGUAGES
+            .iter()
+            .any(|lang| !self.language_images.contains_key(lang));
+        if needs_default {
+            ensure_image(
+                backend.as_ref(),
+                self.directory.as_deref(),
+                &self.image_tag,
+                progress,
+            )
+            .await?;
+        }
+        let mut language_images = HashMap::new();
+        for (lang, image) in self.language_images {
+            ensure_image(
+                backend.as_ref(),
+                image.directory.as_deref(),
+                &image.image_tag,
+                progress,
+            )
+            .await?;
+            language_images.insert(lang, image.image_tag);
+        }
+        for version in self.versions.values().flat_map(HashMap::values) {
+            if let Some(image_tag) = &version.image_tag {
+                backend.image_labels(image_tag).await?;
+            }
+        }
+        let mut languages = self.languages;
+        for lang in LANGUAGES {
+            languages
+                .entry(lang)
+                .or_insert_with(|| LanguageSpec::builtin(lang));
+        }
+        let mut sandbox = DockerSandbox {
+            backend: Arc::from(backend),
+            image_tag: self.image_tag,
+            language_images,
+            versions: self.versions,
+            sandbox_id: new_sandbox_id(),
+            sandbox_dir: self.sandbox_dir,
+            languages,
+            limits: self.limits,
+            network: self.network,
+            transfer: self.transfer,
+            queue: RunQueue::new(self.max_concurrent, self.max_queued),
+            stale_sweep: None,
+        };
+        // leftovers of other sandboxes must never keep this one from starting, a failed sweep is
+        // only reported through stale_sweep
+        if let Some(max_age) = self.stale_age {
+            sandbox.stale_sweep = Some(sandbox.cleanup_stale(max_age).await);
+        }
+        Ok(sandbox)
+    }
+}
+
+// images built from a directory are only rebuilt when their build context changed
+async fn ensure_image(
+    backend: &dyn ContainerBackend,
+    directory: Option<&Path>,
+    image_tag: &str,
+    progress: Option<&BuildProgress>,
+) -> Result<(), SandboxError> {
+    let Some(directory) = directory else {
+        backend.image_labels(image_tag).await?;
+        return Ok(());
+    };
+    let absolute_path = validate_directory(directory)?;
+    let context_hash = hash_build_context(&absolute_path)?;
+    let existing_hash = backend
+        .image_labels(image_tag)
+        .await
+        .ok()
+        .and_then(|mut labels| labels.remove(CONTEXT_HASH_LABEL));
+    if existing_hash.as_deref() != Some(context_hash.as_str()) {
+        let context = WorkDir::new()?;
+        prepare_build_context(&absolute_path, &context_hash, context.path())?;
+        backend
+            .build_image(context.path(), image_tag, progress)
+            .await?;
+    }
+    Ok(())
+}
+
+const BACKEND_ENV: &str = "RUSTYCHAINS_BACKEND";
+
+const LANGUAGES: [Language; 3] = [Language::Python, Language::JavaScript, Language::Java];
+
+fn default_backend(
+    endpoint: Option<DockerEndpoint>,
+) -> Result<Box<dyn ContainerBackend>, SandboxError> {
+    if endpoint.is_some() {
+        return connect_backend(BackendKind::Docker, endpoint);
+    }
+    let kind = match env::var(BACKEND_ENV).as_deref() {
+        Err(_) | Ok("docker") => BackendKind::Docker,
+        Ok("podman") => BackendKind::Podman,
+        Ok("local") => BackendKind::Local,
+        Ok(other) => return Err(SandboxError::UnknownBackend(other.to_owned())),
+    };
+    connect_backend(kind, None)
+}
+
+fn connect_backend(
+    kind: BackendKind,
+    endpoint: Option<DockerEndpoint>,
+) -> Result<Box<dyn ContainerBackend>, SandboxError> {
+    match (kind, endpoint) {
+        (BackendKind::Docker, endpoint) => Ok(Box::new(DockerBackend::connect(
+            endpoint.unwrap_or_default(),
+        )?)),
+        (BackendKind::Podman, Some(DockerEndpoint::Unix(socket))) => {
+            Ok(Box::new(DockerBackend::podman(Some(socket))?))
+        }
+        (BackendKind::Podman, _) => Ok(Box::new(DockerBackend::podman(None)?)),
+        (BackendKind::Local, _) => Ok(Box::new(LocalBackend::new(
+            Isolation::from_env()?,
+            LocalLimits::default(),
+        ))),
     }
 }
 
@@ -202,93 +1493,257 @@ fn validate_directory(dir: &Path) -> Result<PathBuf, SandboxError> {
         })
 }
 
-async fn build_image(docker: &Docker, path: &Path, tag: &str) -> Result<(), SandboxError> {
-    let options = BuildOptions::builder(path.display().to_string())
-        .tag(tag)
-        .build();
-    let mut stream = docker.images().build(&options);
-    while let Some(build_result) = stream.next().await {
-        build_result.map_err(SandboxError::BuildImage)?;
+fn hash_build_context(dir: &Path) -> Result<String, SandboxError> {
+    let mut hasher = Sha256::new();
+    for file in list_files(dir, context_err)? {
+        let contents = fs::read(dir.join(&file)).map_err(|err| SandboxError::ReadBuildContext {
+            path: dir.join(&file),
+            source: err,
+        })?;
+        hasher.update(file.display().to_string().as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
     }
-    Ok(())
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
-async fn exec_container(
-    docker: &Docker,
+fn context_err(path: PathBuf, err: std::io::Error) -> SandboxError {
+    SandboxError::ReadBuildContext { path, source: err }
+}
+
+fn prepare_build_context(
+    dir: &Path,
+    context_hash: &str,
     temp_dir: &Path,
-    image_tag: &str,
-    cmd: &[String],
-    stdin: Option<&str>,
-) -> Result<RunOutput, SandboxError> {
-    let container_id = create_container(docker, temp_dir, image_tag, cmd).await?;
-    let container = docker.containers().get(&container_id);
-    let (reader, mut writer) = container
-        .attach()
-        .await
-        .map_err(|err| SandboxError::AtachToContainer {
-            container_id: container_id.to_owned(),
+) -> Result<(), SandboxError> {
+    for file in list_files(dir, context_err)? {
+        let dest = temp_dir.join(&file);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|err| SandboxError::ReadBuildContext {
+                path: parent.to_path_buf(),
+                source: err,
+            })?;
+        }
+        fs::copy(dir.join(&file), &dest).map_err(|err| SandboxError::ReadBuildContext {
+            path: dir.join(&file),
             source: err,
-        })?
-        .split();
+        })?;
+    }
 
-    container
-        .start()
-        .await
-        .map_err(|err| SandboxError::StartContainer {
-            container_id: container_id.to_owned(),
+    let docker_file = temp_dir.join("Dockerfile");
+    let mut contents =
+        fs::read_to_string(&docker_file).map_err(|err| SandboxError::ReadBuildContext {
+            path: dir.join("Dockerfile"),
             source: err,
         })?;
+    contents.push_str(&format!(
+        "\nLABEL {CONTEXT_HASH_LABEL}=\"{context_hash}\"\n"
+    ));
+    fs::write(&docker_file, contents).map_err(|err| SandboxError::ReadBuildContext {
+        path: docker_file.clone(),
+        source: err,
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Deadline {
+    // everything from creating the container on has to be done by then
+    At { at: Instant, timeout: Duration },
+    // only the time the container spends running counts
+    Run(Duration),
+}
 
-    if let Some(s) = stdin {
-        writer
-            .write_all(s.as_bytes())
+async fn exec_container(
+    backend: &dyn ContainerBackend,
+    spec: &ContainerSpec<'_>,
+    stdin_data: Option<&str>,
+    copy_out_globs: Option<&[String]>,
+    cancel: Option<&CancellationToken>,
+    deadline: Option<Deadline>,
+) -> Result<RunOutput, SandboxError> {
+    let create_start = Instant::now();
+    let create = backend.create_container(spec);
+    tokio::pin!(create);
+    let container_id = match until_stopped(create.as_mut(), cancel, deadline).await {
+        Ok(container_id) => container_id,
+        // the daemon carries on with a create it was already sent, so it is let finish and the
+        // container removed rather than left to the stale sweep
+        Err(err @ (SandboxError::Cancelled | SandboxError::Timeout { .. })) => {
+            if let Ok(container_id) = create.await {
+                let _ = backend.remove_container(&container_id).await;
+            }
+            return Err(err);
+        }
+        Err(err) => return Err(err),
+    };
+    let create = create_start.elapsed();
+    let run_timeout = match deadline {
+        Some(Deadline::Run(timeout)) => Some(timeout),
+        _ => None,
+    };
+    let run = run_container(
+        backend,
+        spec,
+        &container_id,
+        stdin_data,
+        copy_out_globs,
+        run_timeout,
+    );
+    let result = until_stopped(run, cancel, deadline).await;
+
+    let cleanup_start = Instant::now();
+    if result.is_err() {
+        // the container may exit on its own before the kill lands
+        let _ = backend.kill_container(&container_id).await;
+    }
+    let removed = backend.remove_container(&container_id).await;
+    let mut output = result?;
+    removed?;
+    output.timings.create += create;
+    output.timings.cleanup = cleanup_start.elapsed();
+    Ok(output)
+}
+
+// whichever comes first of the run finishing, the token being cancelled or the deadline passing
+async fn until_stopped<F, T>(
+    run: F,
+    cancel: Option<&CancellationToken>,
+    deadline: Option<Deadline>,
+) -> Result<T, SandboxError>
+where
+    F: Future<Output = Result<T, SandboxError>>,
+{
+    let run = async {
+        let Some(Deadline::At { at, timeout }) = deadline else {
+            return run.await;
+        };
+        time::timeout_at(at, run)
             .await
-            .map_err(SandboxError::WriteToStdin)?;
-        writer.flush().await.map_err(SandboxError::WriteToStdin)?;
+            .map_err(|err| SandboxError::Timeout {
+                duration: timeout,
+                source: err,
+            })?
+    };
+    tokio::select! {
+        result = run => result,
+        () = cancelled(cancel) => Err(SandboxError::Cancelled),
     }
+}
 
-    container
-        .wait()
-        .await
-        .map_err(|err| SandboxError::Execute {
-            cmd: cmd.join(" "),
-            source: err,
-        })?;
+async fn run_container(
+    backend: &dyn ContainerBackend,
+    spec: &ContainerSpec<'_>,
+    container_id: &str,
+    stdin_data: Option<&str>,
+    copy_out_globs: Option<&[String]>,
+    run_timeout: Option<Duration>,
+) -> Result<RunOutput, SandboxError> {
+    let mut timings = PhaseTimings::default();
+    let create_start = Instant::now();
+    copy_in(backend, spec, container_id).await?;
+    let AttachedContainer { output, mut stdin } = backend.attach_container(container_id).await?;
+    timings.create = create_start.elapsed();
 
-    let chunks = reader
-        .try_collect::<Vec<_>>()
-        .await
-        .map_err(|err| SandboxError::Execute {
-            cmd: cmd.join(" "),
-            source: err,
-        })?;
+    let run_start = Instant::now();
+    // output is read while the container runs so that chunks are timestamped on arrival
+    let read_output = output
+        .map_ok(|chunk| (run_start.elapsed(), chunk))
+        .try_collect::<Vec<_>>();
+    let run = async {
+        backend.start_container(container_id).await?;
+        if let Some(s) = stdin_data {
+            stdin
+                .write_all(s.as_bytes())
+                .await
+                .map_err(SandboxError::WriteToStdin)?;
+            stdin.flush().await.map_err(SandboxError::WriteToStdin)?;
+        }
+        backend.wait_container(container_id).await
+    };
+    let run = async { futures::try_join!(run, read_output) };
+    let (exit, chunks) = match run_timeout {
+        Some(timeout) => {
+            time::timeout(timeout, run)
+                .await
+                .map_err(|err| SandboxError::Timeout {
+                    duration: timeout,
+                    source: err,
+                })??
+        }
+        None => run.await?,
+    };
+    timings.run = run_start.elapsed();
 
-    remove_container(&container).await?;
-    convert_chunks(&chunks)
+    let collect_start = Instant::now();
+    if spec.transfer == TransferMode::Copy {
+        copy_out(backend, spec, container_id, copy_out_globs).await?;
+    }
+    let mut output = convert_chunks(chunks, exit)?;
+    timings.collect = collect_start.elapsed();
+    output.timings = timings;
+    Ok(output)
 }
 
-fn convert_chunks(chunks: &[TtyChunk]) -> Result<RunOutput, SandboxError> {
-    let stdout = chunks
-        .iter()
-        .filter_map(|chunk| match chunk {
-            StdIn(_) => None,
-            StdOut(bytes) => Some(bytes),
-            StdErr(_) => None,
-        })
-        .flatten()
-        .copied()
-        .collect::<Vec<_>>();
-
-    let stderr = chunks
-        .iter()
-        .filter_map(|chunk| match chunk {
-            StdIn(_) => None,
-            StdOut(_) => None,
-            StdErr(bytes) => Some(bytes),
-        })
-        .flatten()
-        .copied()
-        .collect::<Vec<_>>();
+async fn copy_in(
+    backend: &dyn ContainerBackend,
+    spec: &ContainerSpec<'_>,
+    container_id: &str,
+) -> Result<(), SandboxError> {
+    if spec.transfer == TransferMode::Copy {
+        let archive = pack_sandbox_dir(spec.host_dir, spec.workdir)?;
+        backend.copy_into_container(container_id, archive).await?;
+    }
+    Ok(())
+}
+
+// brings files the container wrote back into the host directory, which later containers and
+// output collection read from; globs of None copies everything
+async fn copy_out(
+    backend: &dyn ContainerBackend,
+    spec: &ContainerSpec<'_>,
+    container_id: &str,
+    globs: Option<&[String]>,
+) -> Result<(), SandboxError> {
+    if globs.is_some_and(<[String]>::is_empty) {
+        return Ok(());
+    }
+    let spool = WorkDir::new()?;
+    let archive = spool.path().join("sandbox.tar");
+    backend
+        .copy_from_container(container_id, spec.workdir, &archive)
+        .await?;
+    unpack_sandbox_archive(&archive, spec.host_dir, globs)
+}
+
+pub(crate) async fn cancelled(token: Option<&CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => future::pending().await,
+    }
+}
+
+fn convert_chunks(
+    chunks: Vec<(Duration, TtyChunk)>,
+    exit: ContainerExit,
+) -> Result<RunOutput, SandboxError> {
+    let mut transcript = Transcript::new();
+    for (elapsed, chunk) in chunks {
+        match chunk {
+            StdIn(_) => {}
+            StdOut(bytes) => transcript.push(StreamKind::Stdout, elapsed, bytes),
+            StdErr(bytes) => transcript.push(StreamKind::Stderr, elapsed, bytes),
+        }
+    }
+    output_from_transcript(transcript, exit)
+}
+
+pub(crate) fn output_from_transcript(
+    transcript: Transcript,
+    exit: ContainerExit,
+) -> Result<RunOutput, SandboxError> {
+    let stdout = transcript.stream_bytes(StreamKind::Stdout);
+    let stderr = transcript.stream_bytes(StreamKind::Stderr);
 
     let stdout = from_utf8(&stdout)
         .map_err(|err| SandboxError::InvalidBytesStdOut { source: err })?
@@ -298,50 +1753,17 @@ fn convert_chunks(chunks: &[TtyChunk]) -> Result<RunOutput, SandboxError> {
         .map_err(|err| SandboxError::InvalidBytesStdErr { source: err })?
         .to_owned();
 
-    Ok(RunOutput { stdout, stderr })
-}
-
-async fn create_container(
-    docker: &Docker,
-    temp_dir: &Path,
-    image_tag: &str,
-    cmd: &[String],
-) -> Result<String, SandboxError> {
-    let mount = format!("{}:/home/sandbox", temp_dir.display());
-    let slice_cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
-    let options = ContainerOptions::builder(image_tag)
-        .volumes(vec![&mount])
-        .working_dir("/home/sandbox")
-        .attach_stdin(true)
-        .attach_stdout(true)
-        .attach_stderr(true)
-        .tty(false)
-        .cmd(slice_cmd)
-        .build();
-    docker.containers().create(&options).await.map_or_else(
-        |err| {
-            Err(SandboxError::CreateContainer {
-                image_tag: image_tag.to_owned(),
-                source: err,
-            })
-        },
-        |result| Ok(result.id),
-    )
-}
-
-async fn remove_container(container: &Container<'_>) -> Result<(), SandboxError> {
-    let options = RmContainerOptions::builder()
-        .volumes(true)
-        .force(true)
-        .build();
-    container
-        .remove(options)
-        .await
-        .map_err(|err| SandboxError::RemoveContainer {
-            container_id: container.id().to_owned(),
-            source: err,
-        })?;
-    Ok(())
+    Ok(RunOutput {
+        stdout,
+        stderr,
+        exit_code: exit.exit_code,
+        oom_killed: exit.oom_killed,
+        files: Vec::new(),
+        usage: exit.usage,
+        timings: PhaseTimings::default(),
+        queue_wait: Duration::ZERO,
+        transcript,
+    })
 }
 
 struct Commands {
@@ -351,40 +1773,64 @@ struct Commands {
 
 struct SandboxFiles {
     host_src: PathBuf,
-    container_src: PathBuf,
-    container_bin: PathBuf,
+    source: String,
+    stem: String,
 }
 
-fn get_source_extension(lang: Language) -> &'static str {
-    match lang {
-        Language::Python => "py",
-        Language::JavaScript => "js",
-        Language::Java => "java",
-    }
+// a requirement such as "3" or "3.12" matches every version it is a dotted
+// prefix of, and the highest of those wins
+pub(crate) fn resolve_version<'a, I>(requirement: &str, available: I) -> Option<&'a str>
+where
+    I: Iterator<Item = &'a str>,
+{
+    available
+        .filter(|version| {
+            version
+                .strip_prefix(requirement)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+        .max_by(|a, b| compare_versions(a, b))
 }
 
-fn get_compiled_extension(lang: Language) -> &'static str {
-    match lang {
-        Language::Python => "py",
-        Language::JavaScript => "js",
-        Language::Java => "",
-    }
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let key = |version: &str| {
+        version
+            .split('.')
+            .map(|part| (part.parse::<u64>().ok(), part.to_owned()))
+            .collect::<Vec<_>>()
+    };
+    key(a).cmp(&key(b))
 }
 
-fn get_compiler(lang: Language) -> Option<&'static str> {
-    match lang {
-        Language::Python => None,
-        Language::JavaScript => None,
-        Language::Java => Some("javac"),
+impl LanguageSpec {
+    pub fn builtin(lang: Language) -> Self {
+        let (extension, compile, run): (_, &[&str], &[&str]) = match lang {
+            Language::Python => ("py", &[], &["python", "{source}"]),
+            Language::JavaScript => ("js", &[], &["node", "{source}"]),
+            Language::Java => ("java", &["javac", "{source}"], &["java", "{stem}"]),
+        };
+        LanguageSpec {
+            extension: extension.to_owned(),
+            compile: compile.iter().map(|&arg| arg.to_owned()).collect(),
+            run: run.iter().map(|&arg| arg.to_owned()).collect(),
+        }
     }
 }
 
-fn get_runner(lang: Language) -> &'static str {
-    match lang {
-        Language::Python => "python",
-        Language::JavaScript => "node",
-        Language::Java => "java",
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), SandboxError> {
+    let copy_err = |path: PathBuf, err| SandboxError::CopyCodeFile {
+        src: path,
+        dest: dest.to_path_buf(),
+        source: err,
+    };
+    for path in list_files(src, copy_err)? {
+        let dest_path = dest.join(&path);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent).map_err(|err| copy_err(src.join(&path), err))?;
+        }
+        fs::copy(src.join(&path), &dest_path).map_err(|err| copy_err(src.join(&path), err))?;
     }
+    Ok(())
 }
 
 fn copy_code_file(src: &Path, dest: &Path) -> Result<(), SandboxError> {
@@ -398,39 +1844,33 @@ fn copy_code_file(src: &Path, dest: &Path) -> Result<(), SandboxError> {
 
 fn get_sandbox_files(
     code_file: &Path,
-    lang: Language,
+    language: &LanguageSpec,
     temp_dir: &Path,
 ) -> Result<SandboxFiles, SandboxError> {
-    let base_file_name: PathBuf = code_file
+    let stem = code_file
         .file_stem()
         .ok_or_else(|| SandboxError::InvalidCodeFile(code_file.to_path_buf()))?
-        .into();
-    let source_ext = get_source_extension(lang);
-    let compiled_ext = get_compiled_extension(lang);
-    let host_src = temp_dir.join(&base_file_name).with_extension(source_ext);
-    let container_src = base_file_name.with_extension(source_ext);
-    let container_bin = base_file_name.with_extension(compiled_ext);
+        .to_string_lossy()
+        .into_owned();
+    let source = format!("{stem}.{}", language.extension);
     Ok(SandboxFiles {
-        host_src,
-        container_src,
-        container_bin,
+        host_src: temp_dir.join(&source),
+        source,
+        stem,
     })
 }
 
-fn get_commands(sandbox_files: &SandboxFiles, lang: Language) -> Commands {
+fn get_commands(sandbox_files: &SandboxFiles, language: &LanguageSpec) -> Commands {
+    let expand = |cmd: &[String]| -> Vec<String> {
+        cmd.iter()
+            .map(|arg| {
+                arg.replace("{source}", &sandbox_files.source)
+                    .replace("{stem}", &sandbox_files.stem)
+            })
+            .collect()
+    };
     Commands {
-        build_cmd: get_build_cmd(&sandbox_files.container_src, lang),
-        run_cmd: get_run_cmd(&sandbox_files.container_bin, lang),
+        build_cmd: expand(&language.compile),
+        run_cmd: expand(&language.run),
     }
 }
-
-fn get_build_cmd(source_file: &Path, lang: Language) -> Vec<String> {
-    get_compiler(lang).map_or_else(Vec::new, |compiler| {
-        vec![compiler.to_owned(), source_file.display().to_string()]
-    })
-}
-
-fn get_run_cmd(compiled_file: &Path, lang: Language) -> Vec<String> {
-    let runner = get_runner(lang).to_owned();
-    vec![runner, compiled_file.display().to_string()]
-}
@@ -0,0 +1,212 @@
+use std::pin::Pin;
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use futures::{AsyncWrite, AsyncWriteExt, StreamExt};
+use shiplift::tty::TtyChunk;
+use tokio::runtime::Handle;
+use tokio::time::{self, Instant};
+
+use crate::backend::ContainerBackend;
+use crate::cleanup::WorkDir;
+use crate::queue::RunPermit;
+use crate::sandbox::{output_from_transcript, PhaseTimings, RunOutput, SandboxError};
+use crate::transcript::{StreamKind, Transcript};
+
+pub const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+pub struct Session<'a> {
+    backend: Arc<dyn ContainerBackend>,
+    container_id: String,
+    removed: bool,
+    output: BoxStream<'a, Result<TtyChunk, SandboxError>>,
+    output_closed: bool,
+    stdin: Option<Pin<Box<dyn AsyncWrite + Send + 'a>>>,
+    stdout: Vec<u8>,
+    transcript: Transcript,
+    timings: PhaseTimings,
+    started: Instant,
+    permit: RunPermit,
+    _temp_dir: WorkDir,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(
+        backend: &Arc<dyn ContainerBackend>,
+        container_id: String,
+        output: BoxStream<'a, Result<TtyChunk, SandboxError>>,
+        stdin: Pin<Box<dyn AsyncWrite + Send + 'a>>,
+        timings: PhaseTimings,
+        permit: RunPermit,
+        temp_dir: WorkDir,
+    ) -> Self {
+        Session {
+            backend: backend.clone(),
+            container_id,
+            removed: false,
+            output,
+            output_closed: false,
+            stdin: Some(stdin),
+            stdout: Vec::new(),
+            transcript: Transcript::new(),
+            timings,
+            started: Instant::now(),
+            permit,
+            _temp_dir: temp_dir,
+        }
+    }
+
+    pub fn container_id(&self) -> &str {
+        &self.container_id
+    }
+
+    pub(crate) fn share_permit(&self) -> RunPermit {
+        self.permit.share()
+    }
+
+    pub async fn write_stdin(&mut self, data: &str) -> Result<(), SandboxError> {
+        let stdin = self.stdin.as_mut().ok_or(SandboxError::StdinClosed)?;
+        stdin
+            .write_all(data.as_bytes())
+            .await
+            .map_err(SandboxError::WriteToStdin)?;
+        stdin.flush().await.map_err(SandboxError::WriteToStdin)
+    }
+
+    // a program that never ends its line must not make the caller buffer its output forever
+    pub async fn read_stdout_line(&mut self) -> Result<Option<String>, SandboxError> {
+        loop {
+            let newline = self.stdout.iter().position(|&byte| byte == b'\n');
+            if newline.unwrap_or(self.stdout.len()) > MAX_LINE_BYTES {
+                self.stdout.clear();
+                return Err(SandboxError::LineTooLong {
+                    limit: MAX_LINE_BYTES,
+                });
+            }
+            if let Some(pos) = newline {
+                let line = self.stdout.drain(..=pos).collect::<Vec<_>>();
+                return decode_line(&line[..pos]).map(Some);
+            }
+            if !self.read_chunk().await? {
+                if self.stdout.is_empty() {
+                    return Ok(None);
+                }
+                let line = std::mem::take(&mut self.stdout);
+                return decode_line(&line).map(Some);
+            }
+        }
+    }
+
+    pub async fn close_stdin(&mut self) -> Result<(), SandboxError> {
+        if let Some(mut stdin) = self.stdin.take() {
+            stdin.close().await.map_err(SandboxError::CloseStdin)?;
+        }
+        Ok(())
+    }
+
+    pub async fn kill(&mut self) -> Result<(), SandboxError> {
+        self.backend.kill_container(&self.container_id).await
+    }
+
+    pub async fn wait(self) -> Result<RunOutput, SandboxError> {
+        let (output, _) = self.finish(None).await?;
+        Ok(output)
+    }
+
+    // kills the program once the deadline passes, the flag tells whether it had to be killed
+    pub(crate) async fn wait_until(
+        self,
+        deadline: Instant,
+    ) -> Result<(RunOutput, bool), SandboxError> {
+        self.finish(Some(deadline)).await
+    }
+
+    async fn finish(
+        mut self,
+        deadline: Option<Instant>,
+    ) -> Result<(RunOutput, bool), SandboxError> {
+        self.stdin = None;
+        let backend = self.backend.clone();
+        let container_id = self.container_id.clone();
+        let (exit, killed) = {
+            let wait = backend.wait_container(&container_id);
+            let read_rest = async {
+                while self.read_chunk().await? {}
+                Ok(())
+            };
+            let exited = async { futures::try_join!(wait, read_rest) };
+            tokio::pin!(exited);
+            let deadline = async {
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => futures::future::pending().await,
+                }
+            };
+            tokio::select! {
+                result = &mut exited => (result?.0, false),
+                () = deadline => {
+                    // the program may exit on its own right at the deadline
+                    let _ = backend.kill_container(&container_id).await;
+                    (exited.await?.0, true)
+                }
+            }
+        };
+        self.timings.run = self.started.elapsed();
+
+        let cleanup_start = Instant::now();
+        backend.remove_container(&container_id).await?;
+        self.removed = true;
+        self.timings.cleanup = cleanup_start.elapsed();
+
+        let transcript = std::mem::take(&mut self.transcript);
+        let mut output = output_from_transcript(transcript, exit)?;
+        output.timings = self.timings;
+        output.queue_wait = self.permit.wait;
+        Ok((output, killed))
+    }
+
+    async fn read_chunk(&mut self) -> Result<bool, SandboxError> {
+        if self.output_closed {
+            return Ok(false);
+        }
+        let Some(chunk) = self.output.next().await.transpose()? else {
+            self.output_closed = true;
+            return Ok(false);
+        };
+        let elapsed = self.started.elapsed();
+        match chunk {
+            TtyChunk::StdIn(_) => {}
+            TtyChunk::StdOut(bytes) => {
+                self.stdout.extend_from_slice(&bytes);
+                self.transcript.push(StreamKind::Stdout, elapsed, bytes);
+            }
+            TtyChunk::StdErr(bytes) => self.transcript.push(StreamKind::Stderr, elapsed, bytes),
+        }
+        Ok(true)
+    }
+}
+
+// a session dropped before wait, by an early return or a panic, still takes its container down
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        let Ok(handle) = Handle::try_current() else {
+            return;
+        };
+        let backend = self.backend.clone();
+        let container_id = std::mem::take(&mut self.container_id);
+        handle.spawn(async move {
+            let _ = backend.kill_container(&container_id).await;
+            let _ = backend.remove_container(&container_id).await;
+        });
+    }
+}
+
+fn decode_line(line: &[u8]) -> Result<String, SandboxError> {
+    from_utf8(line)
+        .map(str::to_owned)
+        .map_err(|err| SandboxError::InvalidBytesStdOut { source: err })
+}
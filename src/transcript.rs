@@ -0,0 +1,93 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamKind {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    pub stream: StreamKind,
+    pub elapsed: Duration,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // for runtimes that only hand back the complete streams once the program exits
+    pub(crate) fn from_streams(stdout: &str, stderr: &str, elapsed: Duration) -> Self {
+        let mut transcript = Transcript::new();
+        for (stream, text) in [(StreamKind::Stdout, stdout), (StreamKind::Stderr, stderr)] {
+            if !text.is_empty() {
+                transcript.push(stream, elapsed, text.as_bytes().to_vec());
+            }
+        }
+        transcript
+    }
+
+    pub fn push(&mut self, stream: StreamKind, elapsed: Duration, bytes: Vec<u8>) {
+        self.entries.push(TranscriptEntry {
+            stream,
+            elapsed,
+            bytes,
+        });
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TranscriptEntry> {
+        self.entries.iter()
+    }
+
+    pub fn stream_bytes(&self, stream: StreamKind) -> Vec<u8> {
+        self.entries()
+            .filter(|entry| entry.stream == stream)
+            .flat_map(|entry| entry.bytes.iter().copied())
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        let bytes = self
+            .entries()
+            .flat_map(|entry| entry.bytes.iter().copied())
+            .collect::<Vec<_>>();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    // a stream switch in the middle of a line starts a new line so each line has one origin
+    pub fn render_timestamped(&self) -> String {
+        let mut rendered = String::new();
+        let mut current = None;
+        for entry in self.entries() {
+            let text = String::from_utf8_lossy(&entry.bytes);
+            for line in text.split_inclusive('\n') {
+                if current.is_some_and(|stream| stream != entry.stream) {
+                    rendered.push('\n');
+                    current = None;
+                }
+                if current.is_none() {
+                    let tag = match entry.stream {
+                        StreamKind::Stdout => "out",
+                        StreamKind::Stderr => "err",
+                    };
+                    let secs = entry.elapsed.as_secs_f64();
+                    let _ = write!(rendered, "[{secs:>9.3}s {tag}] ");
+                }
+                rendered.push_str(line);
+                current = (!line.ends_with('\n')).then_some(entry.stream);
+            }
+        }
+        if current.is_some() {
+            rendered.push('\n');
+        }
+        rendered
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::from_utf8;
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+use wasmtime_wasi::p1::{self, WasiP1Ctx};
+use wasmtime_wasi::p2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+
+use crate::sandbox::{PhaseTimings, ResourceUsage, RunOutput, SandboxError};
+use crate::transcript::Transcript;
+
+pub struct WasmRuntime {
+    engine: Engine,
+}
+
+#[derive(Clone, Debug)]
+pub struct WasmLimits {
+    pub fuel: Option<u64>,
+    pub max_memory_bytes: Option<usize>,
+    pub max_output_bytes: usize,
+}
+
+struct WasmState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+const FUEL_YIELD_INTERVAL: u64 = 10_000;
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        WasmLimits {
+            fuel: None,
+            max_memory_bytes: Some(256 * 1024 * 1024),
+            max_output_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl WasmRuntime {
+    pub fn new() -> Result<Self, SandboxError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|err| SandboxError::WasmEngine(err.into()))?;
+        Ok(WasmRuntime { engine })
+    }
+
+    pub async fn run_module<T>(
+        &self,
+        module_file: T,
+        timeout: Duration,
+        limits: &WasmLimits,
+        stdin: Option<&str>,
+    ) -> Result<RunOutput, SandboxError>
+    where
+        T: AsRef<Path>,
+    {
+        let prepare_start = Instant::now();
+        let module_file = module_file.as_ref();
+        let module = Module::from_file(&self.engine, module_file).map_err(|err| {
+            SandboxError::LoadWasmModule {
+                path: module_file.to_path_buf(),
+                source: err.into(),
+            }
+        })?;
+
+        let stdout = MemoryOutputPipe::new(limits.max_output_bytes);
+        let stderr = MemoryOutputPipe::new(limits.max_output_bytes);
+        let wasi = WasiCtxBuilder::new()
+            .stdin(MemoryInputPipe::new(stdin.unwrap_or_default().to_owned()))
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .arg(module_name(module_file))
+            .build_p1();
+        let mut store_limits = StoreLimitsBuilder::new();
+        if let Some(bytes) = limits.max_memory_bytes {
+            store_limits = store_limits.memory_size(bytes);
+        }
+        let state = WasmState {
+            wasi,
+            limits: store_limits.build(),
+        };
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(limits.fuel.unwrap_or(u64::MAX))
+            .map_err(|err| SandboxError::WasmEngine(err.into()))?;
+        store
+            .fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))
+            .map_err(|err| SandboxError::WasmEngine(err.into()))?;
+        let mut linker = Linker::new(&self.engine);
+        p1::add_to_linker_async(&mut linker, |state: &mut WasmState| &mut state.wasi)
+            .map_err(|err| SandboxError::WasmEngine(err.into()))?;
+
+        let prepare = prepare_start.elapsed();
+        let start = Instant::now();
+        let exec = async {
+            let instance = linker.instantiate_async(&mut store, &module).await?;
+            let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+            start.call_async(&mut store, ()).await
+        };
+        let exit_code =
+            match time::timeout(timeout, exec)
+                .await
+                .map_err(|err| SandboxError::Timeout {
+                    duration: timeout,
+                    source: err,
+                })? {
+                Ok(()) => 0,
+                Err(err) => exit_code(err)?,
+            };
+        let run = start.elapsed();
+
+        let stdout = from_utf8(&stdout.contents())
+            .map_err(|err| SandboxError::InvalidBytesStdOut { source: err })?
+            .to_owned();
+        let stderr = from_utf8(&stderr.contents())
+            .map_err(|err| SandboxError::InvalidBytesStdErr { source: err })?
+            .to_owned();
+        let transcript = Transcript::from_streams(&stdout, &stderr, run);
+        Ok(RunOutput {
+            stdout,
+            stderr,
+            exit_code,
+            oom_killed: false,
+            files: Vec::new(),
+            usage: ResourceUsage {
+                wall_time: Some(run),
+                ..ResourceUsage::default()
+            },
+            timings: PhaseTimings {
+                prepare,
+                run,
+                ..PhaseTimings::default()
+            },
+            queue_wait: Duration::ZERO,
+            transcript,
+        })
+    }
+}
+
+fn module_name(module_file: &Path) -> String {
+    module_file
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .display()
+        .to_string()
+}
+
+fn exit_code(err: wasmtime::Error) -> Result<u64, SandboxError> {
+    if let Some(exit) = err.downcast_ref::<I32Exit>() {
+        return Ok(u64::from(exit.0 as u32));
+    }
+    if let Some(Trap::OutOfFuel) = err.downcast_ref::<Trap>() {
+        return Err(SandboxError::FuelExhausted);
+    }
+    let source: Box<dyn Error + Send + Sync> = err.into();
+    Err(SandboxError::WasmTrap(source))
+}
@@ -1,25 +1,75 @@
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
-use tokio::time::Instant;
+use tokio::time::{self, Instant};
 
-pub use crate::sandbox::Language;
-use crate::sandbox::{DockerSandbox, SandboxError};
+pub use crate::output::{InputFile, OutputFile, OutputLimits};
+pub use crate::sandbox::{CancellationToken, Language};
+use crate::sandbox::{DockerSandbox, ResourceLimits, RunOptions, RunOutput, Sandbox, SandboxError};
+pub use crate::sandbox::{PhaseTimings, ResourceUsage};
+pub use crate::transcript::Transcript;
+pub use crate::wasm::WasmLimits;
+use crate::wasm::WasmRuntime;
 
 pub struct Workflow {
-    sandbox: DockerSandbox,
+    sandbox: Box<dyn Sandbox>,
+    // only built when some step needs it
+    wasm: Option<WasmRuntime>,
     input: Option<String>,
-    steps: Vec<Step>,
+    steps: Vec<StepKind>,
+    // resolved version of each step, for steps that ask for one
+    runtimes: Vec<Option<String>>,
     exports: Vec<Export>,
 }
 
+#[derive(Clone)]
+pub enum StepKind {
+    Sandbox(Step),
+    Wasm(WasmStep),
+    Native(NativeStep),
+}
+
 #[derive(Clone)]
 pub struct Step {
     pub lang: Language,
+    pub version: Option<String>,
     pub code_file: PathBuf,
     pub timeout: Duration,
     pub desc: String,
+    pub outputs: Vec<String>,
+    pub output_limits: OutputLimits,
+    pub publishes: Vec<ArtifactSpec>,
+    pub consumes: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArtifactSpec {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Clone)]
+pub struct WasmStep {
+    pub module_file: PathBuf,
+    pub timeout: Duration,
+    pub limits: WasmLimits,
+    pub desc: String,
+}
+
+type NativeFn = dyn Fn(Option<String>) -> BoxFuture<'static, anyhow::Result<String>> + Send + Sync;
+
+#[derive(Clone)]
+pub struct NativeStep {
+    func: Arc<NativeFn>,
+    pub timeout: Duration,
+    pub desc: String,
 }
 
 #[derive(Clone)]
@@ -36,18 +86,32 @@ pub enum Export {
 }
 
 pub struct WorkflowBuilder {
-    directory: PathBuf,
-    image_tag: String,
+    sandbox: SandboxSource,
     input: Option<String>,
-    steps: Vec<Step>,
+    steps: Vec<StepKind>,
     exports: Vec<Export>,
 }
 
+enum SandboxSource {
+    Docker {
+        directory: PathBuf,
+        image_tag: String,
+    },
+    Custom(Box<dyn Sandbox>),
+}
+
 #[derive(Debug)]
 pub struct StepResult {
     pub step_idx: usize,
     pub stdout: String,
     pub stderr: String,
+    pub exit_code: u64,
+    pub files: Vec<OutputFile>,
+    pub usage: ResourceUsage,
+    pub timings: PhaseTimings,
+    pub queue_wait: Duration,
+    pub transcript: Transcript,
+    pub runtime: Option<String>,
     pub exec_time: Duration,
 }
 
@@ -57,9 +121,19 @@ pub struct ExportResult {
     pub exec_time: Duration,
 }
 
+#[derive(Clone, Debug)]
+pub struct ArtifactRecord {
+    pub name: String,
+    pub step_idx: usize,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
 pub struct WorkflowResult {
     step_results: Vec<StepResult>,
     export_results: Vec<ExportResult>,
+    artifacts: Vec<ArtifactRecord>,
 }
 
 #[derive(Error, Debug)]
@@ -67,6 +141,9 @@ pub enum WorkflowError {
     #[error("failed to init docker sandbox")]
     SandboxInit(#[source] SandboxError),
 
+    #[error("failed to init wasm runtime")]
+    WasmInit(#[source] SandboxError),
+
     #[error("failed to execute step at index {}", .prev_steps_results.len())]
     StepError {
         #[source]
@@ -74,6 +151,39 @@ pub enum WorkflowError {
         prev_steps_results: Vec<StepResult>,
     },
 
+    #[error("step at index {step_idx} consumes artifact {name:?} which no earlier step publishes")]
+    UnknownArtifact { step_idx: usize, name: String },
+
+    #[error("step at index {step_idx} publishes artifact {name:?} from {path:?}, which is not a relative path inside its working directory")]
+    InvalidArtifactPath {
+        step_idx: usize,
+        name: String,
+        path: PathBuf,
+    },
+
+    #[error("step at index {step_idx} publishes artifact {name:?}, but artifact names have to be plain file names")]
+    InvalidArtifactName { step_idx: usize, name: String },
+
+    #[error("step at index {step_idx} requires {lang:?} version {version:?} which the sandbox does not provide")]
+    UnsupportedVersion {
+        step_idx: usize,
+        lang: Language,
+        version: String,
+    },
+
+    #[error("artifact {name:?} is published by more than one step")]
+    DuplicateArtifact { step_idx: usize, name: String },
+
+    #[error("step at index {} did not produce artifact {name:?} at {path:?}", .prev_steps_results.len())]
+    MissingArtifact {
+        name: String,
+        path: PathBuf,
+        prev_steps_results: Vec<StepResult>,
+    },
+
+    #[error("workflow was cancelled before step at index {} completed", .prev_steps_results.len())]
+    Cancelled { prev_steps_results: Vec<StepResult> },
+
     #[error("failed to execute export")]
     ExportError {
         #[source]
@@ -89,8 +199,22 @@ impl Workflow {
         T: AsRef<Path>,
     {
         WorkflowBuilder {
-            directory: directory.as_ref().to_owned(),
-            image_tag: image_tag.to_owned(),
+            sandbox: SandboxSource::Docker {
+                directory: directory.as_ref().to_owned(),
+                image_tag: image_tag.to_owned(),
+            },
+            input: None,
+            steps: Vec::new(),
+            exports: Vec::new(),
+        }
+    }
+
+    pub fn with_sandbox<S>(sandbox: S) -> WorkflowBuilder
+    where
+        S: Sandbox + 'static,
+    {
+        WorkflowBuilder {
+            sandbox: SandboxSource::Custom(Box::new(sandbox)),
             input: None,
             steps: Vec::new(),
             exports: Vec::new(),
@@ -101,7 +225,15 @@ impl Workflow {
         self.input.as_deref()
     }
 
+    // sandbox steps only, as before wasm and native steps existed; step_kinds lists every step
     pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter().filter_map(|step| match step {
+            StepKind::Sandbox(step) => Some(step),
+            StepKind::Wasm(_) | StepKind::Native(_) => None,
+        })
+    }
+
+    pub fn step_kinds(&self) -> impl Iterator<Item = &StepKind> {
         self.steps.iter()
     }
 
@@ -109,23 +241,52 @@ impl Workflow {
         self.exports.iter()
     }
 
-    pub async fn execute(&self) -> Result<WorkflowResult, WorkflowError> {
-        let step_results = self.execute_steps().await?;
+    // a cancelled token kills whatever step is running and stops before the next one
+    pub async fn execute(
+        &self,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<WorkflowResult, WorkflowError> {
+        let cancel = cancel.cloned().unwrap_or_default();
+        let (step_results, artifacts) = self.execute_steps(&cancel).await?;
         let export_results = self.execute_exports().await?;
         Ok(WorkflowResult {
             step_results,
             export_results,
+            artifacts,
         })
     }
 
-    async fn execute_steps(&self) -> Result<Vec<StepResult>, WorkflowError> {
+    async fn execute_steps(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<(Vec<StepResult>, Vec<ArtifactRecord>), WorkflowError> {
         let mut step_results = Vec::<StepResult>::new();
-        for (idx, step) in self.steps().enumerate() {
+        let mut artifacts = Vec::<ArtifactRecord>::new();
+        let mut artifact_contents = HashMap::<String, Vec<u8>>::new();
+        for (idx, step) in self.step_kinds().enumerate() {
+            if cancel.is_cancelled() {
+                return Err(WorkflowError::Cancelled {
+                    prev_steps_results: step_results,
+                });
+            }
             let input = step_results
                 .last()
                 .map_or(self.input(), |last_result| Some(&last_result.stdout));
-            match step.execute(input, idx, &self.sandbox).await {
-                Ok(r) => step_results.push(r),
+            let input_files = step
+                .consumes()
+                .iter()
+                .map(|name| InputFile {
+                    path: PathBuf::from(name),
+                    contents: artifact_contents[name].to_owned(),
+                })
+                .collect();
+            let result = match step.execute(input, input_files, idx, self, cancel).await {
+                Ok(r) => r,
+                Err(SandboxError::Cancelled) => {
+                    return Err(WorkflowError::Cancelled {
+                        prev_steps_results: step_results,
+                    })
+                }
                 Err(err) => {
                     return Err(WorkflowError::StepError {
                         source: err,
@@ -133,8 +294,26 @@ impl Workflow {
                     })
                 }
             };
+            for artifact in step.publishes() {
+                let Some(file) = result.files.iter().find(|file| file.path == artifact.path) else {
+                    return Err(WorkflowError::MissingArtifact {
+                        name: artifact.name.to_owned(),
+                        path: artifact.path.to_owned(),
+                        prev_steps_results: step_results,
+                    });
+                };
+                artifacts.push(ArtifactRecord {
+                    name: artifact.name.to_owned(),
+                    step_idx: idx,
+                    path: artifact.path.to_owned(),
+                    size: file.contents.len() as u64,
+                    sha256: format!("{:x}", Sha256::digest(&file.contents)),
+                });
+                artifact_contents.insert(artifact.name.to_owned(), file.contents.to_owned());
+            }
+            step_results.push(result);
         }
-        Ok(step_results)
+        Ok((step_results, artifacts))
     }
 
     async fn execute_exports(&self) -> Result<Vec<ExportResult>, WorkflowError> {
@@ -149,40 +328,246 @@ impl Step {
     {
         Self {
             lang,
+            version: None,
             code_file: code_file.as_ref().to_owned(),
             timeout,
             desc: desc.to_owned(),
+            outputs: Vec::new(),
+            output_limits: OutputLimits::default(),
+            publishes: Vec::new(),
+            consumes: Vec::new(),
+        }
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_owned());
+        self
+    }
+
+    pub fn publish<T>(mut self, name: &str, path: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        // collected files are named without "." or repeated separators, so the path is
+        // compared in that form
+        let path = path
+            .as_ref()
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        self.publishes.push(ArtifactSpec {
+            name: name.to_owned(),
+            path,
+        });
+        self
+    }
+
+    pub fn consume(mut self, name: &str) -> Self {
+        self.consumes.push(name.to_owned());
+        self
+    }
+
+    pub fn output(mut self, glob: &str) -> Self {
+        self.outputs.push(glob.to_owned());
+        self
+    }
+
+    pub fn output_limits(mut self, limits: OutputLimits) -> Self {
+        self.output_limits = limits;
+        self
+    }
+
+    async fn run(
+        &self,
+        input: Option<&str>,
+        input_files: Vec<InputFile>,
+        runtime: Option<&str>,
+        sandbox: &dyn Sandbox,
+        cancel: &CancellationToken,
+    ) -> Result<RunOutput, SandboxError> {
+        let artifact_globs = self
+            .publishes
+            .iter()
+            .map(|artifact| glob::Pattern::escape(&artifact.path.to_string_lossy()));
+        let options = RunOptions {
+            timeout: self.timeout,
+            stdin: input.map(str::to_owned),
+            input_files,
+            output_globs: self.outputs.iter().cloned().chain(artifact_globs).collect(),
+            output_limits: self.output_limits,
+            limits: ResourceLimits::default(),
+            cancel: Some(cancel.to_owned()),
+            version: runtime.map(str::to_owned),
+        };
+        sandbox
+            .run_code_with_options(&self.code_file, self.lang, &options)
+            .await
+    }
+}
+
+impl WasmStep {
+    pub fn new<T>(module_file: T, timeout: Duration, desc: &str) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        Self {
+            module_file: module_file.as_ref().to_owned(),
+            timeout,
+            limits: WasmLimits::default(),
+            desc: desc.to_owned(),
+        }
+    }
+
+    pub fn limits(mut self, limits: WasmLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    async fn run(
+        &self,
+        input: Option<&str>,
+        wasm: &WasmRuntime,
+    ) -> Result<RunOutput, SandboxError> {
+        wasm.run_module(&self.module_file, self.timeout, &self.limits, input)
+            .await
+    }
+}
+
+impl NativeStep {
+    pub fn new<F, Fut>(func: F, timeout: Duration, desc: &str) -> Self
+    where
+        F: Fn(Option<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<String>> + Send + 'static,
+    {
+        Self {
+            func: Arc::new(move |input| func(input).boxed()),
+            timeout,
+            desc: desc.to_owned(),
+        }
+    }
+
+    async fn run(&self, input: Option<&str>) -> Result<RunOutput, SandboxError> {
+        let start = Instant::now();
+        let stdout = time::timeout(self.timeout, (self.func)(input.map(str::to_owned)))
+            .await
+            .map_err(|err| SandboxError::Timeout {
+                duration: self.timeout,
+                source: err,
+            })?
+            .map_err(|err| SandboxError::NativeStep(err.into()))?;
+        let run = start.elapsed();
+        Ok(RunOutput {
+            transcript: Transcript::from_streams(&stdout, "", run),
+            stdout,
+            stderr: String::new(),
+            exit_code: 0,
+            oom_killed: false,
+            files: Vec::new(),
+            usage: ResourceUsage::default(),
+            timings: PhaseTimings {
+                run,
+                ..PhaseTimings::default()
+            },
+            queue_wait: Duration::ZERO,
+        })
+    }
+}
+
+impl StepKind {
+    pub fn desc(&self) -> &str {
+        match self {
+            StepKind::Sandbox(step) => &step.desc,
+            StepKind::Wasm(step) => &step.desc,
+            StepKind::Native(step) => &step.desc,
+        }
+    }
+
+    pub fn publishes(&self) -> &[ArtifactSpec] {
+        match self {
+            StepKind::Sandbox(step) => &step.publishes,
+            StepKind::Wasm(_) | StepKind::Native(_) => &[],
+        }
+    }
+
+    pub fn consumes(&self) -> &[String] {
+        match self {
+            StepKind::Sandbox(step) => &step.consumes,
+            StepKind::Wasm(_) | StepKind::Native(_) => &[],
         }
     }
 
     async fn execute(
         &self,
         input: Option<&str>,
+        input_files: Vec<InputFile>,
         step_idx: usize,
-        sandbox: &DockerSandbox,
+        workflow: &Workflow,
+        cancel: &CancellationToken,
     ) -> Result<StepResult, SandboxError> {
+        let runtime = workflow.runtimes[step_idx].as_deref();
+        let sandbox = workflow.sandbox.as_ref();
         let start = Instant::now();
-        let output = sandbox
-            .run_code(&self.code_file, self.lang, self.timeout, input)
-            .await?;
+        let output = match self {
+            StepKind::Sandbox(step) => {
+                step.run(input, input_files, runtime, sandbox, cancel)
+                    .await?
+            }
+            // wasm and native steps hold no containers, so dropping them is enough
+            StepKind::Wasm(step) => {
+                let wasm = workflow
+                    .wasm
+                    .as_ref()
+                    .expect("the wasm runtime is built for workflows with wasm steps");
+                or_cancelled(step.run(input, wasm), cancel).await?
+            }
+            StepKind::Native(step) => or_cancelled(step.run(input), cancel).await?,
+        };
         let exec_time = start.elapsed();
         Ok(StepResult {
             step_idx,
             stdout: output.stdout,
             stderr: output.stderr,
+            exit_code: output.exit_code,
+            files: output.files,
+            usage: output.usage,
+            timings: output.timings,
+            queue_wait: output.queue_wait,
+            transcript: output.transcript,
+            runtime: runtime.map(str::to_owned),
             exec_time,
         })
     }
 }
 
+impl From<Step> for StepKind {
+    fn from(step: Step) -> Self {
+        StepKind::Sandbox(step)
+    }
+}
+
+impl From<WasmStep> for StepKind {
+    fn from(step: WasmStep) -> Self {
+        StepKind::Wasm(step)
+    }
+}
+
+impl From<NativeStep> for StepKind {
+    fn from(step: NativeStep) -> Self {
+        StepKind::Native(step)
+    }
+}
+
 impl WorkflowBuilder {
     pub fn input(mut self, value: Option<&str>) -> Self {
         self.input = value.map(|i| i.to_owned());
         self
     }
 
-    pub fn add_step(mut self, step: Step) -> Self {
-        self.steps.push(step);
+    pub fn add_step<S>(mut self, step: S) -> Self
+    where
+        S: Into<StepKind>,
+    {
+        self.steps.push(step.into());
         self
     }
 
@@ -192,18 +577,112 @@ impl WorkflowBuilder {
     }
 
     pub async fn build(self) -> Result<Workflow, WorkflowError> {
-        let sandbox = DockerSandbox::new(&self.directory, &self.image_tag)
-            .await
-            .map_err(WorkflowError::SandboxInit)?;
+        validate_artifacts(&self.steps)?;
+        let sandbox: Box<dyn Sandbox> = match self.sandbox {
+            SandboxSource::Docker {
+                directory,
+                image_tag,
+            } => Box::new(
+                DockerSandbox::new(&directory, &image_tag)
+                    .await
+                    .map_err(WorkflowError::SandboxInit)?,
+            ),
+            SandboxSource::Custom(sandbox) => sandbox,
+        };
+        let runtimes = resolve_runtimes(&self.steps, sandbox.as_ref())?;
+        let wasm = self
+            .steps
+            .iter()
+            .any(|step| matches!(step, StepKind::Wasm(_)))
+            .then(WasmRuntime::new)
+            .transpose()
+            .map_err(WorkflowError::WasmInit)?;
         Ok(Workflow {
             sandbox,
+            wasm,
             input: self.input,
             steps: self.steps,
+            runtimes,
             exports: self.exports,
         })
     }
 }
 
+fn validate_artifacts(steps: &[StepKind]) -> Result<(), WorkflowError> {
+    let mut published = Vec::<&str>::new();
+    for (step_idx, step) in steps.iter().enumerate() {
+        for name in step.consumes() {
+            if !published.contains(&name.as_str()) {
+                return Err(WorkflowError::UnknownArtifact {
+                    step_idx,
+                    name: name.to_owned(),
+                });
+            }
+        }
+        for artifact in step.publishes() {
+            // consumers receive the artifact as a file of that name in their working directory
+            let mut components = Path::new(&artifact.name).components();
+            let plain = matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(name)), None) if name == artifact.name.as_str()
+            );
+            if !plain {
+                return Err(WorkflowError::InvalidArtifactName {
+                    step_idx,
+                    name: artifact.name.to_owned(),
+                });
+            }
+            let relative = artifact
+                .path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if !relative || artifact.path.as_os_str().is_empty() {
+                return Err(WorkflowError::InvalidArtifactPath {
+                    step_idx,
+                    name: artifact.name.to_owned(),
+                    path: artifact.path.to_owned(),
+                });
+            }
+            if published.contains(&artifact.name.as_str()) {
+                return Err(WorkflowError::DuplicateArtifact {
+                    step_idx,
+                    name: artifact.name.to_owned(),
+                });
+            }
+            published.push(&artifact.name);
+        }
+    }
+    Ok(())
+}
+
+fn resolve_runtimes(
+    steps: &[StepKind],
+    sandbox: &dyn Sandbox,
+) -> Result<Vec<Option<String>>, WorkflowError> {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(step_idx, step)| {
+            let StepKind::Sandbox(Step {
+                lang,
+                version: Some(version),
+                ..
+            }) = step
+            else {
+                return Ok(None);
+            };
+            sandbox
+                .resolve_version(*lang, version)
+                .map(Some)
+                .ok_or_else(|| WorkflowError::UnsupportedVersion {
+                    step_idx,
+                    lang: *lang,
+                    version: version.to_owned(),
+                })
+        })
+        .collect()
+}
+
 impl WorkflowResult {
     pub fn step_results(&self) -> impl Iterator<Item = &StepResult> {
         self.step_results.iter()
@@ -213,6 +692,10 @@ impl WorkflowResult {
         self.export_results.iter()
     }
 
+    pub fn artifacts(&self) -> impl Iterator<Item = &ArtifactRecord> {
+        self.artifacts.iter()
+    }
+
     pub fn exec_time(&self) -> Duration {
         let step_time = self.step_results().map(|sr| sr.exec_time).sum::<Duration>();
         let export_time = self
@@ -222,7 +705,25 @@ impl WorkflowResult {
         step_time + export_time
     }
 
+    pub fn resource_usage(&self) -> ResourceUsage {
+        let mut usage = ResourceUsage::default();
+        for step_result in self.step_results() {
+            usage.accumulate(&step_result.usage);
+        }
+        usage
+    }
+
     pub fn output(&self) -> Option<&str> {
         self.step_results.last().map(|r| r.stdout.as_str())
     }
 }
+
+async fn or_cancelled<F>(run: F, cancel: &CancellationToken) -> Result<RunOutput, SandboxError>
+where
+    F: Future<Output = Result<RunOutput, SandboxError>>,
+{
+    tokio::select! {
+        output = run => output,
+        () = cancel.cancelled() => Err(SandboxError::Cancelled),
+    }
+}
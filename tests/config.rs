@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use rustychains::config::{ConfigError, SandboxConfig, CONFIG_ENV};
+use rustychains::sandbox::{DockerSandbox, Language, NetworkPolicy};
+
+#[tokio::test]
+async fn test_sandbox_from_config_file() -> Result<()> {
+    let config = SandboxConfig::load("./example_code/config/sandbox.toml")?;
+    assert_eq!(NetworkPolicy::Disabled, config.network);
+    assert_eq!(Some(512 * 1024 * 1024), config.limits.memory_bytes);
+    assert!(!config.cleanup.on_build);
+    assert_eq!(
+        Some(Path::new("./example_code/config/../../docker")),
+        config.image.directory.as_deref()
+    );
+
+    let sandbox = DockerSandbox::from_config(&config).await?;
+    assert_eq!("sandbox-python", sandbox.image_for(Language::Python));
+    assert_eq!("sandbox", sandbox.image_for(Language::Java));
+    assert_eq!(
+        Some("3.11".to_owned()),
+        sandbox.resolve_version(Language::Python, "3")
+    );
+    let output = sandbox
+        .run_code(
+            "./example_code/sum.py",
+            Language::Python,
+            Duration::from_secs(3),
+            Some("3\n5\n8\n"),
+        )
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+    Ok(())
+}
+
+// the only test in this file that touches the environment
+#[test]
+fn test_config_from_env_path() -> Result<()> {
+    std::env::set_var(CONFIG_ENV, "./example_code/config/sandbox.toml");
+    let config = SandboxConfig::load_from_env_path()?;
+    assert_eq!(NetworkPolicy::Disabled, config.network);
+
+    std::env::remove_var(CONFIG_ENV);
+    assert!(matches!(
+        SandboxConfig::load_from_env_path(),
+        Err(ConfigError::MissingEnv(CONFIG_ENV))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_config_unknown_key() {
+    let result = SandboxConfig::from_toml("[image]\ntag = \"sandbox\"\n\n[limits]\nmemory = 5\n");
+    match result {
+        Err(ConfigError::Parse(err)) => assert!(err.to_string().contains("memory")),
+        other => panic!("expected a parse error, got {other:?}"),
+    }
+
+    let result = SandboxConfig::from_toml("[image]\ntag = \"sandbox\"\n\n[languages.ruby]\nextension = \"rb\"\nrun = [\"ruby\", \"{source}\"]\n");
+    match result {
+        Err(ConfigError::Parse(err)) => assert!(err.to_string().contains("ruby")),
+        other => panic!("expected a parse error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_config_validation_names_key() {
+    let cases = [
+        ("[image]\ntag = \"\"\n", "image.tag"),
+        ("[image]\ntag = \"sandbox\"\n\n[limits]\ncpus = 0.0\n", "limits.cpus"),
+        ("sandbox_dir = \"sandbox\"\n\n[image]\ntag = \"sandbox\"\n", "sandbox_dir"),
+        (
+            "[image]\ntag = \"sandbox\"\n\n[daemon]\nendpoint = \"ftp://example.com\"\n",
+            "daemon.endpoint",
+        ),
+        (
+            "[image]\ntag = \"sandbox\"\n\n[daemon]\nbackend = \"local\"\nendpoint = \"unix:///run/docker.sock\"\n",
+            "daemon.endpoint",
+        ),
+        (
+            "[image]\ntag = \"sandbox\"\ndirectory = \"./docker\"\narchive = \"sandbox.tar\"\n",
+            "image.archive",
+        ),
+        (
+            "[image]\ntag = \"sandbox\"\n\n[image.languages.python]\ntag = \"\"\n",
+            "image.languages.python.tag",
+        ),
+        (
+            "[image]\ntag = \"sandbox\"\n\n[versions.python.\"3.12\"]\n",
+            "versions.python.\"3.12\"",
+        ),
+        (
+            "[image]\ntag = \"sandbox\"\n\n[languages.java]\nextension = \"java\"\nrun = []\n",
+            "languages.java.run",
+        ),
+    ];
+    for (text, expected_key) in cases {
+        match SandboxConfig::from_toml(text) {
+            Err(ConfigError::Invalid { key, .. }) => assert_eq!(expected_key, key),
+            other => panic!("expected {expected_key} to be rejected, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+use rustychains::backend::{Isolation, LocalBackend, LocalLimits};
+use rustychains::judge::{Checker, InteractiveJudge, Judge, Program, TestCase, Verdict};
+use rustychains::judge::{TestDataError, TestSuite};
+use rustychains::mock::{MockResponse, MockSandbox};
+use rustychains::sandbox::DockerSandbox;
+use rustychains::sandbox::Language;
+use rustychains::sandbox::SandboxError;
+use tempdir::TempDir;
+use tokio::time;
+
+async fn local_sandbox() -> Result<DockerSandbox> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    Ok(sandbox)
+}
+
+fn interactor() -> Program {
+    Program::new(Language::Python, "./example_code/interactive/interactor.py")
+}
+
+#[tokio::test]
+async fn test_interactive_accepted() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(
+        Language::Python,
+        "./example_code/interactive/binary_search.py",
+    );
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::Accepted, result.verdict);
+    assert_eq!(0, result.submission.unwrap().exit_code);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_single_concurrent_run() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .max_concurrent_runs(1)
+        .build()
+        .await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(
+        Language::Python,
+        "./example_code/interactive/binary_search.py",
+    );
+    let result = time::timeout(Duration::from_secs(10), judge.judge(&submission, "37\n")).await??;
+    assert_eq!(Verdict::Accepted, result.verdict);
+    // the shared permit is released again once both sides are done
+    let output = time::timeout(
+        Duration::from_secs(10),
+        sandbox.run_code(
+            "./example_code/hello.py",
+            Language::Python,
+            Duration::from_secs(3),
+            None,
+        ),
+    )
+    .await??;
+    assert_eq!(0, output.exit_code);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_wrong_answer() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(
+        Language::Python,
+        "./example_code/interactive/linear_search.py",
+    );
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::WrongAnswer, result.verdict);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_time_limit_exceeded() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge =
+        InteractiveJudge::new(&sandbox, interactor()).time_limit(Duration::from_millis(500));
+    let submission = Program::new(Language::Python, "./example_code/interactive/stalling.py");
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::TimeLimitExceeded, result.verdict);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_rejected_while_submission_hangs() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge =
+        InteractiveJudge::new(&sandbox, interactor()).time_limit(Duration::from_millis(1500));
+    let submission = Program::new(Language::Python, "./example_code/interactive/giving_up.py");
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::WrongAnswer, result.verdict);
+    assert_eq!(1, result.interactor.unwrap().exit_code);
+    assert!(sandbox.containers().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_submission_lingers() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge =
+        InteractiveJudge::new(&sandbox, interactor()).time_limit(Duration::from_millis(1500));
+    let submission = Program::new(Language::Python, "./example_code/interactive/lingering.py");
+    let result = time::timeout(Duration::from_secs(10), judge.judge(&submission, "37\n")).await??;
+    assert_eq!(Verdict::TimeLimitExceeded, result.verdict);
+    assert_eq!(0, result.interactor.unwrap().exit_code);
+    assert!(sandbox.containers().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_invalid_output() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(Language::Python, "./example_code/interactive/garbled.py");
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::WrongAnswer, result.verdict);
+    assert!(sandbox.containers().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_endless_line() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(
+        Language::Python,
+        "./example_code/interactive/endless_line.py",
+    );
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::WrongAnswer, result.verdict);
+    assert!(sandbox.containers().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_runtime_error() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(Language::Python, "./example_code/interactive/crashing.py");
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::RuntimeError, result.verdict);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interactive_compile_error() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let judge = InteractiveJudge::new(&sandbox, interactor());
+    let submission = Program::new(
+        Language::Java,
+        "./example_code/interactive/BrokenGuess.java",
+    );
+    let result = judge.judge(&submission, "37\n").await?;
+    assert_eq!(Verdict::CompileError, result.verdict);
+    assert!(result.compile_error.unwrap().contains("error"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_verdicts() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .on_call(0, MockResponse::stdout("16\n"))
+        .on_call(1, MockResponse::stdout("15\n"))
+        .on_call(
+            2,
+            MockResponse::stdout("").with_delay(Duration::from_secs(1)),
+        )
+        .on_call(3, MockResponse::stdout("").with_exit_code(1))
+        .on_call(
+            4,
+            MockResponse::stdout("")
+                .with_oom_killed()
+                .with_exit_code(137),
+        );
+    let cases = (0..5)
+        .map(|idx| {
+            TestCase::new(
+                &format!("case {idx}"),
+                "3\n5\n8\n",
+                "16\n",
+                Duration::from_millis(100),
+            )
+        })
+        .collect::<Vec<_>>();
+    let report = Judge::new(&sandbox)
+        .judge("./example_code/sum.py", Language::Python, &cases)
+        .await?;
+
+    let verdicts = report.cases().map(|case| case.verdict).collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            Verdict::Accepted,
+            Verdict::WrongAnswer,
+            Verdict::TimeLimitExceeded,
+            Verdict::RuntimeError,
+            Verdict::MemoryLimitExceeded,
+        ],
+        verdicts
+    );
+    assert_eq!(1, report.passed());
+    assert_eq!(Verdict::WrongAnswer, report.verdict());
+    assert_eq!(Some("3\n5\n8\n"), sandbox.calls()[0].stdin.as_deref());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_compile_error() -> Result<()> {
+    let sandbox =
+        MockSandbox::new().fallback(MockResponse::error(|| SandboxError::CompileFailed {
+            exit_code: 1,
+            stderr: "Sum.java:3: error: ';' expected".to_owned(),
+        }));
+    let cases = vec![
+        TestCase::new("first", "3 5 8", "16", Duration::from_secs(3)),
+        TestCase::new("second", "1 1 1", "3", Duration::from_secs(3)),
+    ];
+    let report = Judge::new(&sandbox)
+        .judge("./example_code/Sum.java", Language::Java, &cases)
+        .await?;
+    assert!(report
+        .cases()
+        .all(|case| case.verdict == Verdict::CompileError));
+    assert_eq!(
+        Some("Sum.java:3: error: ';' expected"),
+        report.compile_error()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_invalid_output() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .on_call(0, MockResponse::stdout("16\n"))
+        .on_call(
+            1,
+            MockResponse::error(|| SandboxError::InvalidBytesStdOut {
+                source: String::from_utf8(vec![0xff]).unwrap_err().utf8_error(),
+            }),
+        );
+    let cases = vec![
+        TestCase::new("first", "3\n5\n8\n", "16\n", Duration::from_secs(3)),
+        TestCase::new("second", "3\n5\n8\n", "16\n", Duration::from_secs(3)),
+    ];
+    let report = Judge::new(&sandbox)
+        .judge("./example_code/sum.py", Language::Python, &cases)
+        .await?;
+    let verdicts = report.cases().map(|case| case.verdict).collect::<Vec<_>>();
+    assert_eq!(vec![Verdict::Accepted, Verdict::WrongAnswer], verdicts);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_compiles_once() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let cases = (0..3)
+        .map(|idx| {
+            TestCase::new(
+                &format!("case {idx}"),
+                "3 5 8 ",
+                "16",
+                Duration::from_secs(3),
+            )
+        })
+        .collect::<Vec<_>>();
+    // the jvm reserves far more address space than it uses, which the default limits allow for
+    let report = Judge::new(&sandbox)
+        .judge("./example_code/Sum.java", Language::Java, &cases)
+        .await?;
+    assert_eq!(Verdict::Accepted, report.verdict());
+    for case in report.cases() {
+        let output = case.output.as_ref().unwrap();
+        assert_eq!(Duration::ZERO, output.timings.compile);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_checkers() -> Result<()> {
+    let sandbox = MockSandbox::new().fallback(MockResponse::stdout("0.3333334  1e2\n"));
+    let cases = vec![TestCase::new(
+        "ratio",
+        "1 3\n",
+        "0.333333 100.0",
+        Duration::from_secs(3),
+    )];
+    let exact = Judge::new(&sandbox)
+        .judge("./example_code/hello.py", Language::Python, &cases)
+        .await?;
+    assert_eq!(Verdict::WrongAnswer, exact.verdict());
+
+    let tolerant = Judge::new(&sandbox)
+        .checker(Checker::FloatTolerance(1e-6))
+        .judge("./example_code/hello.py", Language::Python, &cases)
+        .await?;
+    assert_eq!(Verdict::Accepted, tolerant.verdict());
+
+    let sandbox = MockSandbox::new().fallback(MockResponse::stdout("16 \n\n"));
+    let cases = vec![TestCase::new("sum", "", "16", Duration::from_secs(3))];
+    let whitespace = Judge::new(&sandbox)
+        .checker(Checker::IgnoreWhitespace)
+        .judge("./example_code/hello.py", Language::Python, &cases)
+        .await?;
+    assert_eq!(Verdict::Accepted, whitespace.verdict());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_checker_program_in_parallel() -> Result<()> {
+    let sandbox = local_sandbox().await?;
+    let cases = vec![
+        TestCase::new("two", "a b\n", "a b\n", Duration::from_secs(3)),
+        TestCase::new("three", "x y z\n", "z x y\n", Duration::from_secs(3)),
+        TestCase::new("wrong", "x y\n", "x z\n", Duration::from_secs(3)),
+    ];
+    let checker = Program::new(Language::Python, "./example_code/checker_unordered.py");
+    let report = Judge::new(&sandbox)
+        .checker(Checker::Program(checker))
+        .parallelism(3)
+        .judge("./example_code/shuffle.py", Language::Python, &cases)
+        .await?;
+
+    let verdicts = report.cases().map(|case| case.verdict).collect::<Vec<_>>();
+    assert_eq!(
+        vec![Verdict::Accepted, Verdict::Accepted, Verdict::WrongAnswer],
+        verdicts
+    );
+    let message = report.cases().nth(2).unwrap().checker_message.as_deref();
+    assert_eq!(Some("expected x z in any order"), message);
+    Ok(())
+}
+
+#[test]
+fn test_load_suite_from_dir() -> Result<()> {
+    let suite = TestSuite::load("./example_code/testdata/sum")?;
+    let names = suite
+        .cases()
+        .iter()
+        .map(|case| case.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["1", "2", "10"], names);
+    let last = &suite.cases()[2];
+    assert_eq!("100\n-50\n7\n", last.input);
+    assert_eq!("57\n", last.expected);
+    assert_eq!(Duration::from_secs(5), last.timeout);
+    assert_eq!(30, last.points);
+    assert_eq!(Duration::from_secs(3), suite.cases()[0].timeout);
+    assert_eq!(50, suite.max_score());
+    Ok(())
+}
+
+fn write_zip(path: &std::path::Path, files: &[(&str, &str)]) -> Result<()> {
+    let mut archive = zip::ZipWriter::new(File::create(path)?);
+    for (name, contents) in files {
+        archive.start_file(*name, zip::write::SimpleFileOptions::default())?;
+        archive.write_all(contents.as_bytes())?;
+    }
+    archive.finish()?;
+    Ok(())
+}
+
+#[test]
+fn test_load_suite_from_zip() -> Result<()> {
+    let temp_dir = TempDir::new("rustychains-testdata")?;
+    let archive_path = temp_dir.path().join("tests.zip");
+    write_zip(
+        &archive_path,
+        &[
+            ("tests.toml", "[tests.a]\npoints = 4\n"),
+            ("a.in", "1\n2\n3\n"),
+            ("a.out", "6\n"),
+        ],
+    )?;
+    let suite = TestSuite::load(&archive_path)?;
+    assert_eq!(1, suite.cases().len());
+    assert_eq!("6\n", suite.cases()[0].expected);
+    assert_eq!(4, suite.cases()[0].points);
+
+    write_zip(
+        &archive_path,
+        &[("a.in", "1\n"), ("a.out", "1\n"), ("b.out", "0\n")],
+    )?;
+    let result = TestSuite::load(&archive_path);
+    assert!(matches!(result, Err(TestDataError::MissingInput { name }) if name == "b"));
+    Ok(())
+}
+
+#[test]
+fn test_load_suite_natural_order() -> Result<()> {
+    let temp_dir = TempDir::new("rustychains-testdata")?;
+    let names = ["case10", "b", "case2", "10", "2", "case02", "case1"];
+    for name in names {
+        std::fs::write(temp_dir.path().join(format!("{name}.in")), "")?;
+        std::fs::write(temp_dir.path().join(format!("{name}.out")), "")?;
+    }
+    std::fs::write(temp_dir.path().join("generator.bin"), [0xff, 0xfe])?;
+    let suite = TestSuite::load(temp_dir.path())?;
+    let names = suite
+        .cases()
+        .iter()
+        .map(|case| case.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["2", "10", "b", "case1", "case02", "case2", "case10"],
+        names
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_judge_suite_score() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .on_call(0, MockResponse::stdout("16\n"))
+        .on_call(1, MockResponse::stdout("4\n"))
+        .on_call(2, MockResponse::stdout("57\n"));
+    let suite = TestSuite::load("./example_code/testdata/sum")?;
+    let report = Judge::new(&sandbox)
+        .judge_suite("./example_code/sum.py", Language::Python, &suite)
+        .await?;
+    assert_eq!(40, report.score());
+    assert_eq!(50, report.max_score());
+    assert_eq!(Some("100\n-50\n7\n"), sandbox.calls()[2].stdin.as_deref());
+    Ok(())
+}
@@ -1,11 +1,38 @@
+use std::collections::HashMap;
+use std::path::Path;
 use std::time::Duration;
 
 use anyhow::Result;
+use async_trait::async_trait;
+use openssl::asn1::Asn1Time;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{Ssl, SslAcceptor, SslMethod, SslVerifyMode};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+use rustychains::backend::{AttachedContainer, ContainerBackend, ContainerExit, ContainerSpec};
+use rustychains::backend::{ContainerSummary, DaemonVersion, ImageSummary};
+use rustychains::backend::{DockerBackend, Isolation, LocalBackend, LocalLimits};
+use rustychains::cleanup::OWNER_FILE;
+use rustychains::sandbox::BuildProgress;
+use rustychains::sandbox::CancellationToken;
+use rustychains::sandbox::DockerEndpoint;
 use rustychains::sandbox::DockerSandbox;
 use rustychains::sandbox::Language;
+use rustychains::sandbox::LanguageSpec;
+use rustychains::sandbox::LanguageVersion;
+use rustychains::sandbox::OutputLimits;
+use rustychains::sandbox::RunOptions;
 use rustychains::sandbox::SandboxError;
+use rustychains::sandbox::TlsConfig;
+use rustychains::sandbox::TransferMode;
+use rustychains::transcript::{StreamKind, Transcript};
 use serde::Deserialize;
 use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_openssl::SslStream;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct Point {
@@ -237,3 +264,1059 @@ async fn test_java_timeout() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_from_existing_image() -> Result<()> {
+    DockerSandbox::new("./docker", "sandbox").await?;
+    let sandbox = DockerSandbox::from_existing_image("sandbox").await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/hello.py",
+            Language::Python,
+            Duration::from_secs(3),
+            None,
+        )
+        .await?;
+    assert_eq!("Hello World\n", &output.stdout);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_missing_existing_image() -> Result<()> {
+    let result = DockerSandbox::existing_image_builder("rustychains-missing-image")
+        .endpoint(DockerEndpoint::Default)
+        .build()
+        .await;
+    assert!(matches!(result, Err(SandboxError::MissingImage { .. })));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_missing_image_archive() -> Result<()> {
+    let result = DockerSandbox::image_archive_builder("./tests/missing-image.tar", "sandbox")
+        .endpoint(DockerEndpoint::Default)
+        .build()
+        .await;
+    assert!(matches!(result, Err(SandboxError::ReadImageArchive { .. })));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_build_error() -> Result<()> {
+    let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let progress = lines.clone();
+    let result = DockerSandbox::builder("./tests/broken_docker", "sandbox-broken")
+        .endpoint(DockerEndpoint::Default)
+        .on_build_progress(move |line| progress.lock().unwrap().push(line.to_owned()))
+        .build()
+        .await;
+    match result {
+        Err(SandboxError::BuildImage { step, log_tail, .. }) => {
+            assert!(step.is_some_and(|step| step.contains("RUN exit 3")));
+            assert!(!log_tail.is_empty());
+        }
+        _ => panic!("expected build to fail"),
+    }
+    assert!(!lines.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unreachable_daemon() -> Result<()> {
+    let result = DockerSandbox::builder("./docker", "sandbox")
+        .endpoint(DockerEndpoint::Unix("/nonexistent/docker.sock".into()))
+        .build()
+        .await;
+    assert!(matches!(
+        result,
+        Err(SandboxError::DaemonUnreachable { .. })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_endpoint() -> Result<()> {
+    let result = DockerSandbox::existing_image_builder("sandbox")
+        .endpoint(DockerEndpoint::Tcp {
+            url: "not a url".to_owned(),
+            tls: None,
+        })
+        .build()
+        .await;
+    assert!(matches!(result, Err(SandboxError::InvalidEndpoint { .. })));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_tls_files() -> Result<()> {
+    let cert_dir = tempdir::TempDir::new("rustychains-tls")?;
+    for file in ["cert.pem", "key.pem", "ca.pem"] {
+        std::fs::write(cert_dir.path().join(file), "not a certificate")?;
+    }
+    let result = DockerSandbox::existing_image_builder("sandbox")
+        .endpoint(DockerEndpoint::Tcp {
+            url: "tcp://127.0.0.1:2376".to_owned(),
+            tls: Some(TlsConfig {
+                cert_dir: cert_dir.path().to_owned(),
+            }),
+        })
+        .build()
+        .await;
+    assert!(matches!(result, Err(SandboxError::InvalidTlsFiles { .. })));
+    Ok(())
+}
+
+struct Issued {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+fn issue(name: &str, issuer: Option<&Issued>) -> Result<Issued> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+    let mut subject = X509NameBuilder::new()?;
+    subject.append_entry_by_text("CN", name)?;
+    let subject = subject.build();
+    let mut cert = X509Builder::new()?;
+    cert.set_version(2)?;
+    cert.set_subject_name(&subject)?;
+    cert.set_pubkey(&key)?;
+    cert.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+    cert.set_not_after(&*Asn1Time::days_from_now(1)?)?;
+    match issuer {
+        Some(issuer) => {
+            cert.set_issuer_name(issuer.cert.subject_name())?;
+            let san = SubjectAlternativeName::new()
+                .ip("127.0.0.1")
+                .build(&cert.x509v3_context(Some(&issuer.cert), None))?;
+            cert.append_extension(san)?;
+            cert.sign(&issuer.key, MessageDigest::sha256())?;
+        }
+        None => {
+            cert.set_issuer_name(&subject)?;
+            cert.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+            cert.sign(&key, MessageDigest::sha256())?;
+        }
+    }
+    Ok(Issued {
+        cert: cert.build(),
+        key,
+    })
+}
+
+// answers every request with an empty 200, which is all a ping needs
+async fn serve_tls(server: Issued, ca: &Issued) -> Result<u16> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_certificate(&server.cert)?;
+    acceptor.set_private_key(&server.key)?;
+    acceptor.cert_store_mut().add_cert(ca.cert.clone())?;
+    acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let acceptor = acceptor.build();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let ssl = Ssl::new(acceptor.context())?;
+                let mut stream = SslStream::new(ssl, stream)?;
+                std::pin::Pin::new(&mut stream).accept().await?;
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    request.push(stream.read_u8().await?);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK")
+                    .await?;
+                anyhow::Ok(())
+            });
+        }
+    });
+    Ok(port)
+}
+
+fn write_client_certs(dir: &std::path::Path, client: &Issued, ca: &Issued) -> Result<()> {
+    std::fs::write(dir.join("cert.pem"), client.cert.to_pem()?)?;
+    std::fs::write(dir.join("key.pem"), client.key.private_key_to_pem_pkcs8()?)?;
+    std::fs::write(dir.join("ca.pem"), ca.cert.to_pem()?)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tls_endpoint() -> Result<()> {
+    let ca = issue("rustychains test ca", None)?;
+    let rogue_ca = issue("rustychains rogue ca", None)?;
+    let trusted_port = serve_tls(issue("daemon", Some(&ca))?, &ca).await?;
+    let rogue_port = serve_tls(issue("daemon", Some(&rogue_ca))?, &ca).await?;
+    let cert_dir = tempdir::TempDir::new("rustychains-tls")?;
+    write_client_certs(cert_dir.path(), &issue("client", Some(&ca))?, &ca)?;
+    let connect = |port: u16| {
+        DockerBackend::connect(DockerEndpoint::Tcp {
+            url: format!("tcp://127.0.0.1:{port}"),
+            tls: Some(TlsConfig {
+                cert_dir: cert_dir.path().to_owned(),
+            }),
+        })
+    };
+
+    connect(trusted_port)?.ping().await?;
+    // a daemon whose certificate was not issued by ca.pem is never talked to
+    assert!(matches!(
+        connect(rogue_port)?.ping().await,
+        Err(SandboxError::DaemonUnreachable { .. })
+    ));
+    Ok(())
+}
+
+// takes one chunked upload and answers it, handing back what was uploaded
+async fn serve_load(
+    socket: &std::path::Path,
+    response: String,
+) -> Result<tokio::task::JoinHandle<Result<Vec<u8>>>> {
+    let listener = tokio::net::UnixListener::bind(socket)?;
+    Ok(tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n0\r\n\r\n") {
+            request.push(stream.read_u8().await?);
+        }
+        stream.write_all(response.as_bytes()).await?;
+        Ok(request)
+    }))
+}
+
+#[tokio::test]
+async fn test_load_image_streams_archive() -> Result<()> {
+    let dir = tempdir::TempDir::new("rustychains-load")?;
+    let archive = dir.path().join("image.tar");
+    std::fs::write(&archive, "x".repeat(256 * 1024))?;
+    let socket = dir.path().join("docker.sock");
+    let backend = DockerBackend::connect(DockerEndpoint::Unix(socket.clone()))?;
+
+    let body = "{\"stream\":\"Loaded image: sandbox\"}";
+    let server = serve_load(
+        &socket,
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ),
+    )
+    .await?;
+    backend.load_image(&archive).await?;
+    let request = server.await??;
+    assert!(request.starts_with(b"POST /images/load"));
+    let body_start = request
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap();
+    let uploaded = request[body_start..]
+        .iter()
+        .filter(|&&byte| byte == b'x')
+        .count();
+    assert_eq!(256 * 1024, uploaded);
+
+    std::fs::remove_file(&socket)?;
+    let body = "{\"message\":\"archive is not an image\"}";
+    let server = serve_load(
+        &socket,
+        format!(
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ),
+    )
+    .await?;
+    let result = backend.load_image(&archive).await;
+    server.await??;
+    match result {
+        Err(SandboxError::LoadImageFailed { message, .. }) => {
+            assert_eq!("archive is not an image", message)
+        }
+        _ => panic!("expected the daemon to refuse the archive"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_podman_unreachable() -> Result<()> {
+    let backend = DockerBackend::podman(Some("/nonexistent/podman.sock".into()))?;
+    let result = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await;
+    let Err(err @ SandboxError::DaemonUnreachable { .. }) = result else {
+        panic!("expected an unreachable daemon");
+    };
+    assert!(err.to_string().starts_with("podman daemon at "));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_local_backend() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/sum.py",
+            Language::Python,
+            Duration::from_secs(3),
+            Some("3\n5\n8\n"),
+        )
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+    assert!(&output.stderr.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_output_files() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let options = RunOptions::new(Duration::from_secs(3))
+        .stdin(Some("3\n5\n8\n"))
+        .output_glob("reports/*.txt");
+    let output = sandbox
+        .run_code_with_options("./example_code/report.py", Language::Python, &options)
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+    assert_eq!(1, output.files.len());
+    assert_eq!(
+        "reports/summary.txt",
+        output.files[0].path.to_str().unwrap()
+    );
+    assert_eq!(b"count=3\nsum=16\n", output.files[0].contents.as_slice());
+
+    let options = options.output_limits(OutputLimits {
+        max_file_bytes: 4,
+        ..OutputLimits::default()
+    });
+    let result = sandbox
+        .run_code_with_options("./example_code/report.py", Language::Python, &options)
+        .await;
+    assert!(matches!(
+        result,
+        Err(SandboxError::OutputFileTooLarge { limit: 4, .. })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_output_files_skip_links() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let options = RunOptions::new(Duration::from_secs(3))
+        .output_glob("reports/*")
+        .output_glob("reports/**/*.txt");
+    let output = sandbox
+        .run_code_with_options("./example_code/link_report.py", Language::Python, &options)
+        .await?;
+    assert_eq!("done\n", &output.stdout);
+    let paths = output
+        .files
+        .iter()
+        .map(|file| file.path.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["reports/summary.txt"], paths);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_input_files() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let options = RunOptions::new(Duration::from_secs(3)).input_file("numbers.txt", b"3\n5\n8\n");
+    let output = sandbox
+        .run_code_with_options("./example_code/sum_file.py", Language::Python, &options)
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+
+    let options = RunOptions::new(Duration::from_secs(3)).input_file("../numbers.txt", b"");
+    let result = sandbox
+        .run_code_with_options("./example_code/sum_file.py", Language::Python, &options)
+        .await;
+    assert!(matches!(result, Err(SandboxError::InvalidInputFile(_))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resource_usage() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/sum.py",
+            Language::Python,
+            Duration::from_secs(3),
+            Some("3\n5\n8\n"),
+        )
+        .await?;
+    let usage = output.usage;
+    assert!(usage.wall_time.is_some_and(|wall| wall > Duration::ZERO));
+    assert!(usage.cpu_user.is_some());
+    assert!(usage.cpu_system.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_docker_resource_usage() -> Result<()> {
+    let backend = DockerBackend::connect(DockerEndpoint::Default)?;
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/sum.py",
+            Language::Python,
+            Duration::from_secs(3),
+            Some("3\n5\n8\n"),
+        )
+        .await?;
+    let usage = output.usage;
+    assert!(usage.cpu_user.is_some());
+    assert!(usage.cpu_system.is_some());
+    assert!(usage.peak_memory_bytes.is_some_and(|bytes| bytes > 0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bubblewrap_hides_host() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::Bubblewrap, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let manifest = std::fs::canonicalize("Cargo.toml")?;
+    let output = sandbox
+        .run_code(
+            "./example_code/probe_path.py",
+            Language::Python,
+            Duration::from_secs(3),
+            Some(&format!("{}\n", manifest.display())),
+        )
+        .await?;
+    assert_eq!("hidden\n", output.stdout);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_phase_timings() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/Sum.java",
+            Language::Java,
+            Duration::from_secs(10),
+            Some("3 5 8 "),
+        )
+        .await?;
+    let timings = output.timings;
+    assert_eq!("16", &output.stdout);
+    assert!(timings.compile > Duration::ZERO);
+    assert!(timings.run > Duration::ZERO);
+    assert!(timings.total() >= timings.compile + timings.run);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interleaved_transcript() -> Result<()> {
+    let backend = DockerBackend::connect(DockerEndpoint::Default)?;
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/interleave.py",
+            Language::Python,
+            Duration::from_secs(3),
+            None,
+        )
+        .await?;
+    assert_eq!("reading input\ndone\n", &output.stdout);
+    assert_eq!("warning: input is empty\n", &output.stderr);
+    assert_eq!(
+        "reading input\nwarning: input is empty\ndone\n",
+        output.transcript.render()
+    );
+    let elapsed = output
+        .transcript
+        .entries()
+        .map(|entry| entry.elapsed)
+        .collect::<Vec<_>>();
+    assert!(elapsed.windows(2).all(|pair| pair[0] <= pair[1]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_local_interleaved_transcript() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/interleave.py",
+            Language::Python,
+            Duration::from_secs(3),
+            None,
+        )
+        .await?;
+    assert_eq!("reading input\ndone\n", &output.stdout);
+    assert_eq!("warning: input is empty\n", &output.stderr);
+    // separate pipes only keep the order within each stream
+    for (stream, expected) in [
+        (StreamKind::Stdout, "reading input\ndone\n"),
+        (StreamKind::Stderr, "warning: input is empty\n"),
+    ] {
+        assert_eq!(
+            expected.as_bytes(),
+            output.transcript.stream_bytes(stream).as_slice()
+        );
+        let elapsed = output
+            .transcript
+            .entries()
+            .filter(|entry| entry.stream == stream)
+            .map(|entry| entry.elapsed)
+            .collect::<Vec<_>>();
+        assert!(elapsed.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_render_timestamped_transcript() {
+    let mut transcript = Transcript::new();
+    transcript.push(
+        StreamKind::Stdout,
+        Duration::from_millis(5),
+        b"progress: ".to_vec(),
+    );
+    transcript.push(
+        StreamKind::Stderr,
+        Duration::from_millis(12),
+        b"oops\n".to_vec(),
+    );
+    transcript.push(
+        StreamKind::Stdout,
+        Duration::from_millis(20),
+        b"50%\ndone".to_vec(),
+    );
+    assert_eq!(
+        "[    0.005s out] progress: \n\
+         [    0.012s err] oops\n\
+         [    0.020s out] 50%\n\
+         [    0.020s out] done\n",
+        transcript.render_timestamped()
+    );
+}
+
+#[tokio::test]
+async fn test_interactive_session() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let mut session = sandbox
+        .start_session("./example_code/guess.py", Language::Python)
+        .await?;
+    assert!(session.read_stdout_line().await?.is_some());
+    let (mut low, mut high) = (1, 100);
+    let mut guesses = 0;
+    loop {
+        let guess = (low + high) / 2;
+        guesses += 1;
+        session.write_stdin(&format!("{guess}\n")).await?;
+        match session.read_stdout_line().await?.as_deref() {
+            Some("higher") => low = guess + 1,
+            Some("lower") => high = guess - 1,
+            Some("correct") => break,
+            other => panic!("unexpected reply {other:?}"),
+        }
+    }
+    assert!(guesses <= 7);
+    let output = session.wait().await?;
+    assert_eq!(0, output.exit_code);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_close_stdin_and_kill() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let mut session = sandbox
+        .start_session("./example_code/guess.py", Language::Python)
+        .await?;
+    session.write_stdin("50\n").await?;
+    session.close_stdin().await?;
+    assert!(matches!(
+        session.write_stdin("25\n").await,
+        Err(SandboxError::StdinClosed)
+    ));
+    let output = session.wait().await?;
+    assert_eq!("guess a number between 1 and 100\nlower\n", &output.stdout);
+    assert_eq!("gave up after 1 guesses\n", &output.stderr);
+
+    let mut session = sandbox
+        .start_session("./example_code/guess.py", Language::Python)
+        .await?;
+    session.kill().await?;
+    let output = session.wait().await?;
+    assert_eq!(128 + 9, output.exit_code);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dropped_session_removes_container() -> Result<()> {
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .cleanup_stale_on_build(None)
+        .build()
+        .await?;
+    let session = sandbox
+        .start_session("./example_code/guess.py", Language::Python)
+        .await?;
+    assert_eq!(1, sandbox.containers().await?.len());
+    drop(session);
+    let removed = async {
+        while !sandbox.containers().await?.is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        anyhow::Ok(())
+    };
+    tokio::time::timeout(Duration::from_secs(10), removed).await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zero_concurrent_runs() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let result = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .max_concurrent_runs(0)
+        .build()
+        .await;
+    assert!(matches!(result, Err(SandboxError::NoConcurrentRuns)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrency_limit() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .max_concurrent_runs(1)
+        .max_queued_runs(1)
+        .build()
+        .await?;
+    let run = || {
+        sandbox.run_code(
+            "./example_code/slow_echo.py",
+            Language::Python,
+            Duration::from_secs(10),
+            Some("hello\n"),
+        )
+    };
+    let first = run();
+    let second = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        run().await
+    };
+    let third = async {
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        run().await
+    };
+    let (first, second, third) = tokio::join!(first, second, third);
+    let (first, second) = (first?, second?);
+    assert_eq!("hello\n", &first.stdout);
+    assert_eq!("hello\n", &second.stdout);
+    assert!(first.queue_wait < Duration::from_secs(1));
+    assert!(second.queue_wait > Duration::from_secs(2));
+    assert!(matches!(
+        third,
+        Err(SandboxError::Overloaded { max_queued: 1 })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_run() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    let cancel = CancellationToken::new();
+    let options = RunOptions::new(Duration::from_secs(10))
+        .stdin(Some("hello\n"))
+        .cancel(cancel.clone());
+    let start = std::time::Instant::now();
+    let run =
+        sandbox.run_code_with_options("./example_code/slow_echo.py", Language::Python, &options);
+    let cancel_later = async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        cancel.cancel();
+    };
+    let (result, ()) = tokio::join!(run, cancel_later);
+    assert!(matches!(result, Err(SandboxError::Cancelled)));
+    assert!(start.elapsed() < Duration::from_secs(3));
+    Ok(())
+}
+
+// a daemon that takes its time creating containers, so that a run can be stopped mid-create
+struct SlowCreate(LocalBackend);
+
+#[async_trait]
+impl ContainerBackend for SlowCreate {
+    fn endpoint(&self) -> String {
+        self.0.endpoint()
+    }
+
+    async fn ping(&self) -> Result<(), SandboxError> {
+        self.0.ping().await
+    }
+
+    async fn version(&self) -> Result<DaemonVersion, SandboxError> {
+        self.0.version().await
+    }
+
+    async fn image_labels(&self, image_tag: &str) -> Result<HashMap<String, String>, SandboxError> {
+        self.0.image_labels(image_tag).await
+    }
+
+    async fn build_image(
+        &self,
+        context: &Path,
+        image_tag: &str,
+        progress: Option<&BuildProgress>,
+    ) -> Result<(), SandboxError> {
+        self.0.build_image(context, image_tag, progress).await
+    }
+
+    async fn load_image(&self, archive: &Path) -> Result<(), SandboxError> {
+        self.0.load_image(archive).await
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>, SandboxError> {
+        self.0.list_containers().await
+    }
+
+    async fn list_dangling_images(&self) -> Result<Vec<ImageSummary>, SandboxError> {
+        self.0.list_dangling_images().await
+    }
+
+    async fn remove_image(&self, image_id: &str) -> Result<(), SandboxError> {
+        self.0.remove_image(image_id).await
+    }
+
+    async fn create_container(&self, spec: &ContainerSpec<'_>) -> Result<String, SandboxError> {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        self.0.create_container(spec).await
+    }
+
+    async fn attach_container<'a>(
+        &'a self,
+        container_id: &str,
+    ) -> Result<AttachedContainer<'a>, SandboxError> {
+        self.0.attach_container(container_id).await
+    }
+
+    async fn copy_into_container(
+        &self,
+        container_id: &str,
+        archive: Vec<u8>,
+    ) -> Result<(), SandboxError> {
+        self.0.copy_into_container(container_id, archive).await
+    }
+
+    async fn copy_from_container(
+        &self,
+        container_id: &str,
+        path: &Path,
+        dest: &Path,
+    ) -> Result<(), SandboxError> {
+        self.0.copy_from_container(container_id, path, dest).await
+    }
+
+    async fn start_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        self.0.start_container(container_id).await
+    }
+
+    async fn wait_container(&self, container_id: &str) -> Result<ContainerExit, SandboxError> {
+        self.0.wait_container(container_id).await
+    }
+
+    async fn kill_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        self.0.kill_container(container_id).await
+    }
+
+    async fn remove_container(&self, container_id: &str) -> Result<(), SandboxError> {
+        self.0.remove_container(container_id).await
+    }
+}
+
+#[tokio::test]
+async fn test_cancel_during_create() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(SlowCreate(backend))
+        .build()
+        .await?;
+    let cancel = CancellationToken::new();
+    let options = RunOptions::new(Duration::from_secs(10)).cancel(cancel.clone());
+    let run = sandbox.run_code_with_options("./example_code/hello.py", Language::Python, &options);
+    let cancel_later = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+    };
+    let (result, ()) = tokio::join!(run, cancel_later);
+    assert!(matches!(result, Err(SandboxError::Cancelled)));
+    assert!(sandbox.containers().await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timeout_removes_container() -> Result<()> {
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .cleanup_stale_on_build(None)
+        .build()
+        .await?;
+    let result = sandbox
+        .run_code(
+            "./example_code/hang.py",
+            Language::Python,
+            Duration::from_secs(1),
+            None,
+        )
+        .await;
+    assert!(matches!(result, Err(SandboxError::Timeout { .. })));
+    assert!(sandbox.containers().await?.is_empty());
+    let survivors = std::process::Command::new("pgrep")
+        .args(["-f", "^\\S*python\\S* \\S*hang\\.py$"])
+        .output()?;
+    assert!(survivors.stdout.is_empty());
+    Ok(())
+}
+
+fn hostname() -> Result<String> {
+    Ok(std::fs::read_to_string("/proc/sys/kernel/hostname")?
+        .trim()
+        .to_owned())
+}
+
+fn marked_dir(name: &str, owner: Option<u32>) -> Result<std::path::PathBuf> {
+    let dir = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(dir.join("work"))?;
+    if let Some(pid) = owner {
+        std::fs::write(dir.join(OWNER_FILE), format!("{}\n{pid}\n", hostname()?))?;
+    }
+    Ok(dir)
+}
+
+#[tokio::test]
+async fn test_cleanup_stale_temp_dirs() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .cleanup_stale_on_build(None)
+        .build()
+        .await?;
+    assert!(sandbox.stale_sweep().is_none());
+    // no process can have a pid above the kernel's limit of 2^22, so this owner is surely gone
+    let gone = 0x7ffffffe;
+    let pid = std::process::id();
+    let stale = marked_dir(&format!("rustychains-7ffffffe.stale-{pid:x}"), Some(gone))?;
+    let fresh = marked_dir(&format!("rustychains-7ffffffe.fresh-{pid:x}"), Some(gone))?;
+    let live = marked_dir(&format!("rustychains-{pid:x}.live"), Some(pid))?;
+    // a name alone, such as that of an unrelated directory, never gets anything removed
+    let unmarked = marked_dir(&format!("rustychains-abc.old-{pid:x}"), None)?;
+    let two_hours_ago = std::time::SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+    for dir in [&stale, &live, &unmarked] {
+        std::fs::File::open(dir)?.set_modified(two_hours_ago)?;
+    }
+
+    let report = sandbox.cleanup_stale(Duration::from_secs(60 * 60)).await;
+    let exists = [
+        stale.exists(),
+        fresh.exists(),
+        live.exists(),
+        unmarked.exists(),
+    ];
+    for dir in [&fresh, &live, &unmarked] {
+        std::fs::remove_dir_all(dir)?;
+    }
+    report?;
+    assert_eq!([false, true, true, true], exists);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stale_sweep_on_build() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .backend(backend)
+        .build()
+        .await?;
+    assert!(matches!(sandbox.stale_sweep(), Some(Ok(_))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_copy_transfer_mode() -> Result<()> {
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .transfer_mode(TransferMode::Copy)
+        .build()
+        .await?;
+    let output = sandbox
+        .run_code(
+            "./example_code/Sum.java",
+            Language::Java,
+            Duration::from_secs(10),
+            Some("3 5 8 "),
+        )
+        .await?;
+    assert_eq!("16", &output.stdout);
+
+    let options = RunOptions::new(Duration::from_secs(3))
+        .stdin(Some("3\n5\n8\n"))
+        .output_glob("reports/*.txt");
+    let output = sandbox
+        .run_code_with_options("./example_code/report.py", Language::Python, &options)
+        .await?;
+    assert_eq!(1, output.files.len());
+    assert_eq!(b"count=3\nsum=16\n", output.files[0].contents.as_slice());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_language_image() -> Result<()> {
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .language_image(Language::Python, "sandbox")
+        .build()
+        .await?;
+    assert_eq!("sandbox", sandbox.image_for(Language::Python));
+    let output = sandbox
+        .run_code(
+            "./example_code/sum.py",
+            Language::Python,
+            Duration::from_secs(3),
+            Some("3\n5\n8\n"),
+        )
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_language_images_without_default() -> Result<()> {
+    let backend = LocalBackend::new(Isolation::from_env()?, LocalLimits::default());
+    let sandbox = DockerSandbox::builder("./no-such-directory", "sandbox")
+        .backend(backend)
+        .language_dockerfile(
+            Language::Python,
+            "./docker-languages/python",
+            "sandbox-python",
+        )
+        .language_dockerfile(
+            Language::JavaScript,
+            "./docker-languages/javascript",
+            "sandbox-javascript",
+        )
+        .language_dockerfile(Language::Java, "./docker-languages/java", "sandbox-java")
+        .build()
+        .await?;
+    assert_eq!("sandbox-java", sandbox.image_for(Language::Java));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_language_dockerfile() -> Result<()> {
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .language_dockerfile(
+            Language::JavaScript,
+            "./docker-languages/javascript",
+            "sandbox-javascript",
+        )
+        .build()
+        .await?;
+    assert_eq!(
+        "sandbox-javascript",
+        sandbox.image_for(Language::JavaScript)
+    );
+    assert_eq!("sandbox", sandbox.image_for(Language::Python));
+    let output = sandbox
+        .run_code(
+            "./example_code/sum.js",
+            Language::JavaScript,
+            Duration::from_secs(3),
+            Some("3\n5\n8\n"),
+        )
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_language_version() -> Result<()> {
+    let python3 = LanguageSpec {
+        extension: "py".to_owned(),
+        compile: Vec::new(),
+        run: vec!["python3".to_owned(), "{source}".to_owned()],
+    };
+    let sandbox = DockerSandbox::builder("./docker", "sandbox")
+        .language_version(
+            Language::Python,
+            "3.12",
+            LanguageVersion {
+                spec: Some(python3),
+                image_tag: None,
+            },
+        )
+        .build()
+        .await?;
+    assert_eq!(
+        Some("3.12".to_owned()),
+        sandbox.resolve_version(Language::Python, "3")
+    );
+    assert_eq!(None, sandbox.resolve_version(Language::Python, "3.1"));
+
+    let options = RunOptions::new(Duration::from_secs(3))
+        .stdin(Some("3\n5\n8\n"))
+        .version("3");
+    let output = sandbox
+        .run_code_with_options("./example_code/sum.py", Language::Python, &options)
+        .await?;
+    assert_eq!("16\n", &output.stdout);
+
+    let options = RunOptions::new(Duration::from_secs(3)).version("2");
+    let result = sandbox
+        .run_code_with_options("./example_code/sum.py", Language::Python, &options)
+        .await;
+    assert!(matches!(
+        result,
+        Err(SandboxError::UnsupportedVersion { .. })
+    ));
+    Ok(())
+}
@@ -1,10 +1,20 @@
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Error;
 use anyhow::Result;
+use rustychains::mock::{MockResponse, MockSandbox};
+use rustychains::sandbox::SandboxError;
+use rustychains::workflow::CancellationToken;
 use rustychains::workflow::Language;
+use rustychains::workflow::NativeStep;
+use rustychains::workflow::ResourceUsage;
 use rustychains::workflow::Step;
+use rustychains::workflow::WasmLimits;
+use rustychains::workflow::WasmStep;
 use rustychains::workflow::Workflow;
+use rustychains::workflow::WorkflowError;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -47,7 +57,7 @@ async fn test_workflow_success() -> Result<()> {
         .build()
         .await?;
 
-    let result = workflow.execute().await?;
+    let result = workflow.execute(None).await?;
     let output = result
         .output()
         .ok_or_else(|| Error::msg("Workflow did not produce any output"))?;
@@ -57,3 +67,568 @@ async fn test_workflow_success() -> Result<()> {
     assert!(result.exec_time() <= Duration::from_secs(12));
     Ok(())
 }
+
+#[tokio::test]
+async fn test_mock_workflow_chains_input() -> Result<()> {
+    let sandbox = Arc::new(
+        MockSandbox::new()
+            .on_file("./example_code/sum.py", MockResponse::stdout("16\n"))
+            .on_call(1, MockResponse::stdout("32\n").with_exit_code(3)),
+    );
+    let workflow = Workflow::with_sandbox(Arc::clone(&sandbox))
+        .input(Some("3\n5\n8\n"))
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/sum.py",
+            Duration::from_secs(3),
+            "sum numbers",
+        ))
+        .add_step(Step::new(
+            Language::JavaScript,
+            "./example_code/move_point.js",
+            Duration::from_secs(3),
+            "double the sum",
+        ))
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await?;
+    assert_eq!(Some("32\n"), result.output());
+    let exit_codes = result
+        .step_results()
+        .map(|r| r.exit_code)
+        .collect::<Vec<_>>();
+    assert_eq!(vec![0, 3], exit_codes);
+    let stdins = sandbox
+        .calls()
+        .into_iter()
+        .map(|call| call.stdin)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![Some("3\n5\n8\n".to_owned()), Some("16\n".to_owned())],
+        stdins
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_workflow_step_error() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .on_call(0, MockResponse::stdout("first\n"))
+        .on_call(
+            1,
+            MockResponse::error(|| {
+                SandboxError::InvalidCodeFile("./example_code/missing.py".into())
+            }),
+        );
+    let workflow = Workflow::with_sandbox(sandbox)
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/hello.py",
+            Duration::from_secs(3),
+            "first step",
+        ))
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/missing.py",
+            Duration::from_secs(3),
+            "failing step",
+        ))
+        .build()
+        .await?;
+
+    match workflow.execute(None).await {
+        Err(WorkflowError::StepError {
+            source: SandboxError::InvalidCodeFile(_),
+            prev_steps_results,
+        }) => assert_eq!("first\n", prev_steps_results[0].stdout),
+        _ => panic!("expected the second step to fail"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_workflow_timeout() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .fallback(MockResponse::stdout("late\n").with_delay(Duration::from_millis(200)));
+    let workflow = Workflow::with_sandbox(sandbox)
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/slow_echo.py",
+            Duration::from_millis(50),
+            "slow step",
+        ))
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await;
+    assert!(matches!(
+        result,
+        Err(WorkflowError::StepError {
+            source: SandboxError::Timeout { .. },
+            ..
+        })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_workflow_cancel() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .on_call(0, MockResponse::stdout("first\n"))
+        .fallback(MockResponse::stdout("late\n").with_delay(Duration::from_secs(10)));
+    let workflow = Workflow::with_sandbox(sandbox)
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/hello.py",
+            Duration::from_secs(3),
+            "first step",
+        ))
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/slow_echo.py",
+            Duration::from_secs(20),
+            "slow step",
+        ))
+        .build()
+        .await?;
+
+    let cancel = CancellationToken::new();
+    let cancel_later = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+    };
+    let (result, ()) = tokio::join!(workflow.execute(Some(&cancel)), cancel_later);
+    match result {
+        Err(WorkflowError::Cancelled { prev_steps_results }) => {
+            assert_eq!(1, prev_steps_results.len());
+            assert_eq!("first\n", prev_steps_results[0].stdout);
+        }
+        _ => panic!("expected the workflow to be cancelled"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_workflow_language_versions() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .version(Language::Python, "3.9")
+        .version(Language::Python, "3.12")
+        .version(Language::Java, "11")
+        .fallback(MockResponse::stdout("ok\n"));
+    let workflow = Workflow::with_sandbox(sandbox)
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/hello.py",
+                Duration::from_secs(3),
+                "legacy step",
+            )
+            .version("3.9"),
+        )
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/echo.py",
+                Duration::from_secs(3),
+                "latest step",
+            )
+            .version("3"),
+        )
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/echo.py",
+            Duration::from_secs(3),
+            "default step",
+        ))
+        .build()
+        .await?;
+    let result = workflow.execute(None).await?;
+    let runtimes = result
+        .step_results()
+        .map(|step_result| step_result.runtime.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(vec![Some("3.9"), Some("3.12"), None], runtimes);
+
+    let sandbox = MockSandbox::new().version(Language::Java, "11");
+    let result = Workflow::with_sandbox(sandbox)
+        .add_step(
+            Step::new(
+                Language::Java,
+                "./example_code/Sum.java",
+                Duration::from_secs(3),
+                "java step",
+            )
+            .version("21"),
+        )
+        .build()
+        .await;
+    match result {
+        Err(WorkflowError::UnsupportedVersion {
+            step_idx, version, ..
+        }) => {
+            assert_eq!(0, step_idx);
+            assert_eq!("21", version);
+        }
+        _ => panic!("expected java 21 to be rejected"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wasm_step_mixed_with_sandbox_steps() -> Result<()> {
+    let sandbox = MockSandbox::new().on_call(0, MockResponse::stdout("16\n"));
+    let workflow = Workflow::with_sandbox(sandbox)
+        .input(Some("3\n5\n8\n"))
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/sum.py",
+            Duration::from_secs(3),
+            "sum numbers",
+        ))
+        .add_step(WasmStep::new(
+            "./example_code/echo.wat",
+            Duration::from_secs(3),
+            "echo the sum",
+        ))
+        .build()
+        .await?;
+    assert_eq!(
+        vec!["sum numbers"],
+        workflow
+            .steps()
+            .map(|step| step.desc.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(2, workflow.step_kinds().count());
+
+    let result = workflow.execute(None).await?;
+    assert_eq!(Some("16\n"), result.output());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wasm_step_fuel_exhausted() -> Result<()> {
+    let limits = WasmLimits {
+        fuel: Some(1_000_000),
+        ..WasmLimits::default()
+    };
+    let workflow = Workflow::with_sandbox(MockSandbox::new())
+        .add_step(
+            WasmStep::new("./example_code/spin.wat", Duration::from_secs(3), "spin").limits(limits),
+        )
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await;
+    assert!(matches!(
+        result,
+        Err(WorkflowError::StepError {
+            source: SandboxError::FuelExhausted,
+            ..
+        })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wasm_step_timeout() -> Result<()> {
+    let workflow = Workflow::with_sandbox(MockSandbox::new())
+        .add_step(WasmStep::new(
+            "./example_code/spin.wat",
+            Duration::from_millis(200),
+            "spin",
+        ))
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await;
+    assert!(matches!(
+        result,
+        Err(WorkflowError::StepError {
+            source: SandboxError::Timeout { .. },
+            ..
+        })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_native_step() -> Result<()> {
+    let point = serde_json::to_string(&Point { x: 2, y: 5 })?;
+    let workflow =
+        Workflow::with_sandbox(MockSandbox::new().on_call(0, MockResponse::stdout("9\n")))
+            .input(Some(&point))
+            .add_step(NativeStep::new(
+                |input: Option<String>| async move {
+                    let point = serde_json::from_str::<Point>(&input.unwrap_or_default())?;
+                    Ok(format!("{}\n", point.x + point.y))
+                },
+                Duration::from_secs(1),
+                "add coordinates",
+            ))
+            .add_step(Step::new(
+                Language::Python,
+                "./example_code/echo.py",
+                Duration::from_secs(3),
+                "echo the sum",
+            ))
+            .build()
+            .await?;
+
+    let result = workflow.execute(None).await?;
+    assert_eq!(Some("9\n"), result.output());
+    assert_eq!(2, result.step_results().count());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_native_step_error() -> Result<()> {
+    let workflow = Workflow::with_sandbox(MockSandbox::new())
+        .input(Some("not json"))
+        .add_step(NativeStep::new(
+            |input: Option<String>| async move {
+                let point = serde_json::from_str::<Point>(&input.unwrap_or_default())?;
+                Ok(format!("{}\n", point.x))
+            },
+            Duration::from_secs(1),
+            "parse point",
+        ))
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await;
+    assert!(matches!(
+        result,
+        Err(WorkflowError::StepError {
+            source: SandboxError::NativeStep(_),
+            ..
+        })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_step_output_files() -> Result<()> {
+    let sandbox = MockSandbox::new().on_call(
+        0,
+        MockResponse::stdout("16\n").with_file("reports/summary.txt", b"sum=16\n"),
+    );
+    let workflow = Workflow::with_sandbox(sandbox)
+        .input(Some("3\n5\n8\n"))
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/report.py",
+                Duration::from_secs(3),
+                "write report",
+            )
+            .output("reports/*.txt"),
+        )
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await?;
+    let step_result = result.step_results().next().unwrap();
+    assert_eq!(1, step_result.files.len());
+    assert_eq!(b"sum=16\n", step_result.files[0].contents.as_slice());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_step_artifacts() -> Result<()> {
+    let sandbox = Arc::new(
+        MockSandbox::new()
+            .on_call(
+                0,
+                MockResponse::stdout("16\n").with_file("reports/summary.txt", b"sum=16\n"),
+            )
+            .on_call(1, MockResponse::stdout("sum=16\n")),
+    );
+    let workflow = Workflow::with_sandbox(Arc::clone(&sandbox))
+        .input(Some("3\n5\n8\n"))
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/report.py",
+                Duration::from_secs(3),
+                "write report",
+            )
+            .publish("summary.txt", "./reports//summary.txt"),
+        )
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/echo.py",
+                Duration::from_secs(3),
+                "read report",
+            )
+            .consume("summary.txt"),
+        )
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await?;
+    let artifact = result.artifacts().next().unwrap();
+    assert_eq!("summary.txt", artifact.name);
+    assert_eq!(0, artifact.step_idx);
+    assert_eq!(7, artifact.size);
+    assert_eq!(
+        "00517065c7847cdffcdc8c97bb7d95f846ad3c3b5afc4eaa042625d35b0da8a2",
+        artifact.sha256
+    );
+    let consumed = &sandbox.calls()[1].input_files;
+    assert_eq!(1, consumed.len());
+    assert_eq!(Path::new("summary.txt"), consumed[0].path);
+    assert_eq!(b"sum=16\n", consumed[0].contents.as_slice());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_artifact_path() -> Result<()> {
+    let result = Workflow::with_sandbox(MockSandbox::new())
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/report.py",
+                Duration::from_secs(3),
+                "write report",
+            )
+            .publish("summary.txt", "../summary.txt"),
+        )
+        .build()
+        .await;
+    assert!(matches!(
+        result,
+        Err(WorkflowError::InvalidArtifactPath { step_idx: 0, .. })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_artifact_name() -> Result<()> {
+    for name in [
+        "../summary.txt",
+        "reports/summary.txt",
+        "/summary.txt",
+        "summary.txt/",
+        ".",
+        "",
+    ] {
+        let result = Workflow::with_sandbox(MockSandbox::new())
+            .add_step(
+                Step::new(
+                    Language::Python,
+                    "./example_code/report.py",
+                    Duration::from_secs(3),
+                    "write report",
+                )
+                .publish(name, "summary.txt"),
+            )
+            .build()
+            .await;
+        assert!(
+            matches!(
+                result,
+                Err(WorkflowError::InvalidArtifactName { step_idx: 0, .. })
+            ),
+            "{name:?}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unknown_artifact() -> Result<()> {
+    let result = Workflow::with_sandbox(MockSandbox::new())
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/echo.py",
+                Duration::from_secs(3),
+                "read report",
+            )
+            .consume("summary.txt"),
+        )
+        .build()
+        .await;
+    assert!(matches!(
+        result,
+        Err(WorkflowError::UnknownArtifact { step_idx: 0, .. })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_missing_artifact() -> Result<()> {
+    let workflow = Workflow::with_sandbox(MockSandbox::new().fallback(MockResponse::stdout("")))
+        .add_step(
+            Step::new(
+                Language::Python,
+                "./example_code/hello.py",
+                Duration::from_secs(3),
+                "produce nothing",
+            )
+            .publish("summary.txt", "reports/summary.txt"),
+        )
+        .build()
+        .await?;
+    let result = workflow.execute(None).await;
+    assert!(matches!(result, Err(WorkflowError::MissingArtifact { .. })));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workflow_resource_usage() -> Result<()> {
+    let usage = |cpu_ms, memory| ResourceUsage {
+        cpu_user: Some(Duration::from_millis(cpu_ms)),
+        peak_memory_bytes: Some(memory),
+        ..ResourceUsage::default()
+    };
+    let sandbox = MockSandbox::new()
+        .on_call(0, MockResponse::stdout("1\n").with_usage(usage(20, 4096)))
+        .on_call(1, MockResponse::stdout("2\n").with_usage(usage(30, 1024)));
+    let workflow = Workflow::with_sandbox(sandbox)
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/hello.py",
+            Duration::from_secs(3),
+            "first",
+        ))
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/echo.py",
+            Duration::from_secs(3),
+            "second",
+        ))
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await?;
+    let total = result.resource_usage();
+    assert_eq!(Some(Duration::from_millis(50)), total.cpu_user);
+    assert_eq!(Some(4096), total.peak_memory_bytes);
+    assert_eq!(None, total.io_read_bytes);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_step_phase_timings() -> Result<()> {
+    let sandbox = MockSandbox::new()
+        .fallback(MockResponse::stdout("1\n").with_delay(Duration::from_millis(20)));
+    let workflow = Workflow::with_sandbox(sandbox)
+        .add_step(Step::new(
+            Language::Python,
+            "./example_code/hello.py",
+            Duration::from_secs(3),
+            "hello",
+        ))
+        .build()
+        .await?;
+
+    let result = workflow.execute(None).await?;
+    let step_result = result.step_results().next().unwrap();
+    assert_eq!(Duration::from_millis(20), step_result.timings.run);
+    assert!(step_result.exec_time >= step_result.timings.total());
+    Ok(())
+}